@@ -1,170 +1,441 @@
 use core::time;
-use std::{io::{self, Error, ErrorKind}, net::UdpSocket, process::Child, sync::mpsc::Sender, thread};
+use std::{cell::Cell, io::{self, Error, ErrorKind, Write}, net::{IpAddr, Ipv6Addr, SocketAddr}, path::PathBuf, process::Child, sync::mpsc::Sender, thread, time::{Duration, Instant}};
 use base64::{prelude::BASE64_STANDARD, Engine};
-use ratatui::{buffer::Buffer, crossterm::event::{self, poll, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, layout::Rect, style::Stylize, symbols::border, text::Line, widgets::{Block, Paragraph, Widget}, Frame};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use chrono::{DateTime, Local};
+use ratatui::{buffer::Buffer, crossterm::event::{self, poll, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, layout::{Position, Rect}, style::{Color, Style, Stylize}, symbols::border, text::{Line, Span}, widgets::{Block, Clear, Padding, Paragraph, Widget}, Frame};
 //use aes_gcm::Aes256Gcm;
 
-use crate::{crypt::convert_to_32_bytes, server, yggdrasil};
+use crate::{config::{Config, Density, Follow, Page, Separator, Timestamps}, crypt::convert_to_32_bytes, delivery::{self, Outbox}, emoji, groups::{self, Groups}, notify::{self, highlight, mask, Keywords, Level}, prefix::Piece, presence::{self, Change, Presence}, protocol::{self, LeaveReason, Packet, HEARTBEAT, MAX_USERNAME}, roster::Roster, sent::{self, Sent}, server, stats::Stats, status::{self, Segment}, theme::{self, Colors, Theme}, throttle::Throttle, transport::{self, LocalTransport, Transport}, typing::{self, Typing}, wrap, yggdrasil};
+
+/// How many unreadable packets it takes before the user is warned about them.
+const UNREADABLE_WARNING: usize = 3;
+/// How long a first Ctrl+C waits for the second one.
+const QUIT_WINDOW: Duration = Duration::from_secs(2);
+/// How long to wait for the server to answer our join before saying so.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Rows the history keeps however tall the input gets: its borders and one
+/// row of messages.
+const MIN_HISTORY_HEIGHT: usize = 3;
+/// How long `/theme preview` shows a theme for.
+const PREVIEW_LENGTH: Duration = Duration::from_secs(5);
+/// How long the input box stays red after something typed in was refused.
+const ERROR_LENGTH: Duration = Duration::from_secs(1);
+/// How much of a message a reply quotes.
+const QUOTE_LENGTH: usize = 40;
+/// How soon a message has to follow the one above from the same sender to
+/// go under it without the name, with `merge_senders` on.
+const MERGE_WINDOW: Duration = Duration::from_secs(120);
+/// How far ahead of ours a sender's clock can be before their times are
+/// taken as wrong.
+const CLOCK_SLACK: Duration = Duration::from_secs(2);
+/// Messages are relayed as they come, so one that says it was written
+/// longer ago than this has a sender whose clock is behind.
+const MAX_DELAY: Duration = Duration::from_secs(300);
 
 pub struct App {
     ui: UI,
     connectaddr: String,
-    socket: UdpSocket,
+    transport: Box<dyn Transport>,
+    // where we ended up sending from, known once connected
+    localaddr: Option<SocketAddr>,
     exit: bool,
-    yggdr: Child,
-    servershutter: Option<Sender<()>>,
+    // None with --offline, nothing to tear down then
+    yggdr: Option<Child>,
+    servertx: Option<Sender<server::Command>>,
+    last_input: Instant,
+    away: bool,
+    // set when we went away on our own because of idling, cleared by the next keypress
+    auto_away: bool,
+    away_after: Option<Duration>,
+    history_limit: usize,
+    page: Page,
+    // whether new lines bring the view down to them
+    auto_scroll: Follow,
+    confirm_quit: bool,
+    // when Ctrl+C was pressed once, waiting to be pressed again
+    quit_pressed: Option<Instant>,
+    outbox: Outbox,
+    gutter: bool,
+    // how many cells of a name go in front of each message, 0 for all of it
+    name_width: usize,
+    message_format: Vec<Piece>,
+    timestamps: Timestamps,
+    // which preset F5 switches away from, None while the settings are the config's own
+    density: Option<Density>,
+    time_format: String,
+    // room kept on the right of the history for `timestamps = right`, a space and the time
+    stamp_width: usize,
+    compact_newlines: bool,
+    // how many rows of input are shown at most before the oldest scroll away
+    input_rows: usize,
+    notify_level: Level,
+    // levels set for single rooms, by room key
+    notify_rooms: Vec<(String, Level)>,
+    groups: Groups,
+    // who sent the newest line of the history and when, if it's a message
+    last_sender: Option<(String, Instant)>,
+    merge_senders: bool,
+    // joins and leaves only show in the users panel
+    quiet: bool,
+    separators: Separator,
+    separator_gap: Option<Duration>,
+    // who sent the newest message and when, whatever came after it
+    last_chat: Option<(String, Instant)>,
+    colors: Colors,
+    theme: Theme,
+    background: Option<Color>,
+    // a theme shown for a moment over the one in use
+    preview: Option<(Theme, Instant)>,
+    // when something typed in was last refused, until the next keypress
+    input_error: Option<Instant>,
+    // when we asked to join, until the server answers or we gave up waiting
+    joining: Option<Instant>,
+    // when the last heartbeat went out
+    pinged: Instant,
+    stats: Stats,
+    // messages in the history that can be replied to, oldest first
+    said: Vec<Said>,
+    // the first history line of the message being replied to
+    selected: Option<usize>,
+    // who wrote the message kept above the history, and what it says
+    pinned: Option<(String, String)>,
+    status: Vec<Segment>,
+    // when we last told the room we're typing, None once we told it we stopped
+    typing_sent: Option<Instant>,
+    typing_refresh: Duration,
+    send_on_ctrl_enter: bool,
+    read_receipts: bool,
+    // long packets go out deflated
+    compress: bool,
+    // the first line of the newest message from others we said we've seen
+    read_sent: Option<usize>,
+    // typing and away updates, so they don't flood the room
+    throttle: Throttle,
+    // room keys and addresses from --servers still to try if this room stops answering
+    fallbacks: Vec<(String, String)>,
+    // what we typed in, and where it's saved if it's saved at all
+    sent: Sent,
+    sent_path: Option<PathBuf>,
+    // rooms tried since the last one that answered, and whether Esc hid that
+    reconnects: usize,
+    reconnect_hidden: bool,
+    // the host took us out of the room, anything sent would be dropped
+    kicked: bool,
+    // the name we asked the server for, until it renames us or says no
+    renaming: Option<String>,
+}
+
+/// A message in the history, kept to be picked for a reply.
+struct Said {
+    // number of its first history line
+    line: usize,
+    username: String,
+    text: String,
+    // how many history lines it takes
+    lines: usize,
+    // how many spans of the first line are the name in front of the text
+    prefix: usize,
+    // who has seen it, for our own messages
+    read_by: Vec<String>,
 }
 
 struct UI {
     username: String,
     roomkey: String,
-    roomusers: Vec<Line<'static>>,
+    topic: String,
+    roomusers: Roster,
     history: Vec<Line<'static>>,
+    // times shown right of the first row of these history lines, by line number
+    stamps: Vec<(usize, String)>,
+    // how many lines were dropped from the front of the history, so lines can
+    // be found again by their number
+    trimmed: usize,
     input: String,
     showkey: bool,
-    showusers: bool
+    showusers: bool,
+    showaddr: bool,
+    // do not disturb: no bell, and the view holds still while messages pile up as unread
+    dnd: bool,
+    showstats: bool,
+    keywords: Keywords,
+    filter: Keywords,
+    border: border::Set,
+    presence: Presence,
+    typing: Typing,
+    // packets we couldn't make sense of
+    unreadable: usize,
+    // how many rows the history is scrolled up from the newest one, kept in a
+    // cell since drawing at a new width moves it
+    scroll: Cell<usize>,
+    // lines that came in while scrolled up
+    unread: usize,
+    // rows of a long draft scrolled back over, set back to the most there are when drawing
+    input_scroll: Cell<usize>,
+    // text area of the history box and where the cursor goes, as of the last draw.
+    // No cursor while it's scrolled out of the input box
+    history_size: Cell<(usize, usize)>,
+    cursor: Cell<Option<Position>>
 }
 
 
 
 impl App {
 
-    pub fn create_room(username: String, port: String) -> Result<Self, Error> {
-        let (connectaddr, yggdr, servershutter) = server::create()?;
-        let roomkeybytes = convert_to_32_bytes(connectaddr.clone()); // gg(g) in the end
-        let socket = UdpSocket::bind(format!("[::]:{}", port))?;
-        
-        Ok(Self {
-            ui: UI {
-                username: username.clone(),
-                roomkey: BASE64_STANDARD.encode(roomkeybytes),
-                roomusers: vec![],
-                history: Vec::new(),
-                input: String::new(),
-                showkey: true,
-                showusers: true
-            },
-            connectaddr: connectaddr.clone(),
-            socket,
-            exit: false,
-            yggdr,
-            servershutter: Some(servershutter)
-        })
+    pub fn create_room(username: String, port: String, topic: String, config: Config) -> Result<Self, Error> {
+        let bind = config.bind;
+        let (yggaddr, localaddr, yggdr, servertx) = server::create(topic, bind, config.presence_timeout)?;
+        let roomkeybytes = convert_to_32_bytes(yggaddr); // gg(g) in the end
+        // --bind moved the server, our own client talks to it from anywhere
+        let transport = transport::bind(config.transport, IpAddr::V6(Ipv6Addr::UNSPECIFIED), &port)?;
+
+        let mut app = Self::new(username, BASE64_STANDARD.encode(roomkeybytes), localaddr, transport, Some(yggdr), Some(servertx), config);
+        if let Some(ip) = bind.filter(|ip| !ip.is_loopback()) {
+            app.notice(format!("The room is listening on {}, anyone who can reach that address can join it without the room key", ip));
+        }
+        Ok(app)
     }
 
-    pub fn join_room(username: String, roomkey: String, port: String) -> Result<Self, Error> {
+    /// Joins the first room key, keeping the rest to fall back on.
+    pub fn join_room(username: String, roomkeys: Vec<String>, port: String, config: Config) -> Result<Self, Error> {
+        // a bad key is refused before anything gets started that would need cleaning up
+        let mut fallbacks = roomkeys.iter().map(|roomkey| decode_roomkey(roomkey)).collect::<Result<Vec<_>, _>>()?;
+        fallbacks.reverse();
+        let (roomkey, connectaddr) = fallbacks.pop().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No room key to join"))?;
+
         let yggdr = yggdrasil::start()?;
         let _ = yggdrasil::get_ipv6();
-
-        let decodedroomkey = match BASE64_STANDARD.decode(roomkey.clone()) {
-            Ok(decoded) => decoded,
-            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e))
-        };
-
-        let connectaddr = match String::from_utf8(decodedroomkey.clone()) {
-            Ok(decoded) => decoded.replace("g", ""),
-            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e))
-        };
         
         //let roomkeybtes = turn_to_32_bytes(connectaddr.clone());
 
-        let socket = match UdpSocket::bind(format!("[::]:{}", port)) {
-            Ok(s) => s,
-            Err(e) => return Err(e)
-        };
+        let ip = config.bind.unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        let transport = transport::bind(config.transport, ip, &port)?;
+
+        let mut app = Self::new(username, roomkey, connectaddr, transport, Some(yggdr), None, config);
+        app.fallbacks = fallbacks;
+        Ok(app)
+    }
 
-        Ok(Self {
+    /// A room that exists only on this machine, for trying out the interface.
+    /// Neither Yggdrasil nor the network is touched, see `LocalTransport`.
+    pub fn offline(username: String, config: Config) -> Self {
+        let mut app = Self::new(username, String::new(), "offline".to_string(), Box::new(LocalTransport::default()), None, None, config);
+        app.ui.showkey = false;
+        app.notice(format!("Offline, nothing you send leaves this machine. {} repeats it back.", transport::ECHO));
+        app
+    }
+
+    fn new(username: String, roomkey: String, connectaddr: String, transport: Box<dyn Transport>, yggdr: Option<Child>, servertx: Option<Sender<server::Command>>, config: Config) -> Self {
+        let sent_path = if config.save_history { sent::path() } else { None };
+        let sent = match sent_path.as_deref().map(Sent::load) {
+            Some(Ok(sent)) => sent,
+            Some(Err(e)) => {
+                tracing::warn!(error = %e, "failed to read the sent history");
+                Sent::default()
+            }
+            None => Sent::default()
+        };
+        Self {
             ui: UI {
-                username: username.clone(),
-                roomkey: roomkey.clone(),
-                roomusers: vec![],
+                username,
+                roomkey,
+                topic: String::new(),
+                roomusers: Roster::default(),
                 history: Vec::new(),
+                stamps: Vec::new(),
+                trimmed: 0,
                 input: String::new(),
-                showkey: true,
-                showusers: true
+                showkey: config.show_key,
+                showusers: config.show_users,
+                showaddr: false,
+                dnd: false,
+                showstats: false,
+                keywords: config.keywords,
+                filter: config.filter,
+                border: config.border,
+                presence: Presence::default(),
+                typing: Typing::default(),
+                unreadable: 0,
+                scroll: Cell::new(0),
+                unread: 0,
+                history_size: Cell::new((0, 0)),
+                input_scroll: Cell::new(0),
+                cursor: Cell::new(None)
             },
             connectaddr,
-            socket,
+            transport,
+            localaddr: None,
             exit: false,
             yggdr,
-            servershutter: None
-        })
+            servertx,
+            last_input: Instant::now(),
+            away: false,
+            auto_away: false,
+            away_after: config.away_after,
+            history_limit: config.history_limit,
+            page: config.page,
+            auto_scroll: config.auto_scroll,
+            confirm_quit: config.confirm_quit,
+            quit_pressed: None,
+            outbox: Outbox::default(),
+            gutter: config.gutter,
+            name_width: config.name_width,
+            message_format: config.message_format,
+            timestamps: config.timestamps,
+            density: config.density,
+            stamp_width: 1 + time_width(&config.time_format),
+            time_format: config.time_format,
+            compact_newlines: config.compact_newlines,
+            input_rows: config.input_rows,
+            last_sender: None,
+            merge_senders: config.merge_senders,
+            quiet: config.quiet,
+            separators: config.separators,
+            separator_gap: config.separator_gap,
+            last_chat: None,
+            notify_level: config.notify_level,
+            notify_rooms: config.notify_rooms,
+            groups: config.groups,
+            colors: config.color.unwrap_or_else(Colors::from_env),
+            theme: config.theme,
+            background: config.background,
+            preview: None,
+            input_error: None,
+            joining: None,
+            pinged: Instant::now(),
+            stats: Stats::default(),
+            said: Vec::new(),
+            selected: None,
+            pinned: None,
+            status: config.status,
+            typing_sent: None,
+            typing_refresh: config.typing_refresh,
+            send_on_ctrl_enter: config.send_on_ctrl_enter,
+            read_receipts: config.read_receipts,
+            compress: config.compress,
+            read_sent: None,
+            throttle: Throttle::new(config.control_interval),
+            fallbacks: Vec::new(),
+            reconnects: 0,
+            reconnect_hidden: false,
+            kicked: false,
+            renaming: None,
+            sent,
+            sent_path
+        }
     }
 
-
     pub fn run(&mut self, terminal: &mut ratatui::DefaultTerminal) -> io::Result<()> {
         let mut error: Option<Error>;
+        // This had to be changed
+        if self.yggdr.is_some() {
+            thread::sleep(time::Duration::from_millis(3000));
+        }
+
         // Attempt to establish a connection to the specified address
-        match self.socket.connect(self.connectaddr.clone()) {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(e);
+        match self.transport.connect(&self.connectaddr) {
+            Ok(()) => {
+                self.localaddr = self.transport.local_addr().ok();
+                tracing::info!(server = %self.connectaddr, local = ?self.localaddr, "connected");
+
+                // Send the username as the initial message to the server
+                error = self.send(Packet::Join(self.ui.username.clone())).err();
+                self.joining = Some(Instant::now());
             }
-        }
-        
-        match self.socket.set_nonblocking(true) {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(e);
+            Err(_) if !self.fallbacks.is_empty() => {
+                error = None;
+                self.fail_over();
             }
+            Err(e) => return Err(Error::new(e.kind(), format!("Failed to connect to the room at {}: {}", self.connectaddr, e)))
         }
 
-        let mut buffer = [0; 10240]; // Storing incoming data here. this limit may change
-
-        // This had to be changed
-        thread::sleep(time::Duration::from_millis(3000));
-
-        // Send the username as the initial message to the server
-        error = self.socket.send(self.ui.username.as_bytes()).err();
-
         // Main loop that runs until the exit flag is set
         while !self.exit {
-            // Attempt to receive data from the socket
-            match self.socket.recv_from(buffer.as_mut()) {
-                Ok((size, _)) => {
-                    // Check if the received data is smaller than 12 bytes, indicating a username
-                    if size < 12 {
-                        let username = match String::from_utf8(buffer[..size].to_vec()) {
-                            Ok(username) => username,
-                            Err(_) => {
-                                error = Some(Error::new(ErrorKind::Other, "An unexpected behaved connection occured when getting join information. Blossom will close itself for security reasons."));
-                                break;
-                            }
-                        };
-                        // Add the new user to the room users list and history
-                        self.ui.roomusers.push(Line::from(username.clone()).red());
-                        self.ui.history.append(&mut vec![Line::from(vec![username.to_owned().red(), " joined the room".red()])]);
-                    } else {
-
-                        // ENCRYPTION IS DEPRECATED
-                        // Decrypt the received message
-                        //let decrypted =crypt::decrypt(&self.cipher, buffer[..size].as_ref()).unwrap_or("Failed to decrypt this message".to_string());
-
-                        let decrypted = String::from_utf8(buffer[..size].to_vec()).unwrap_or("Failed to get message".to_string());
-                        // Split the decrypted message into username and message parts
-                        let (username, message) = match decrypted.split_once('|') {
-                            Some((username, message)) => (username, message),
-                            None => {
-                                error = Some(Error::new(ErrorKind::Other, "An unexpected behaved connection occured when processing decrypted message. Blossom will close itself for security reasons."));
-                                break;
-                            }
-                        };
-
-                        // Add the message to the chat history
-                        self.ui.history.append(&mut vec![Line::from(vec!["[".cyan(), username.to_owned().cyan(), "] ".cyan(), message.to_owned().gray()])]);
+            // Attempt to receive data from the server
+            match self.transport.try_recv() {
+                Ok(Some(packet)) => {
+                    tracing::debug!(size = packet.len(), "received a packet");
+                    self.stats.received(packet.len());
+                    // Anyone can throw bytes at our port, so malformed packets are dropped
+                    // instead of closing the app
+                    match Packet::decode(&packet) {
+                        Some(packet) => {
+                            // the server always answers a join with at least our own join
+                            self.joining = None;
+                            self.reconnects = 0;
+                            self.handle_packet(packet);
+                        }
+                        None => {
+                            tracing::warn!(size = packet.len(), tag = ?packet.first(), "dropped an unreadable packet");
+                            self.ui.unreadable += 1;
+                        }
                     }
                 }
-                // Handle the case where the socket would block, indicating no data is available
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // No data is available
+                Ok(None) => {
                     // Can perform other tasks here if needed
                 }
-                // Handle any other errors that occur during reception
+                // A datagram too big for the buffer got cut off, don't try to make sense of it
+                Err(ref e) if e.kind() == ErrorKind::InvalidData => {
+                    tracing::warn!(error = %e, "dropped a packet");
+                    self.ui.unreadable += 1;
+                }
+                // Handle any other errors that occur during reception. Printing here would
+                // land in the middle of the UI, so the error is returned once the terminal
+                // has been restored
+                Err(e) if !self.fallbacks.is_empty() => {
+                    tracing::warn!(error = %e, "lost the connection to the server");
+                    self.fail_over();
+                }
                 Err(e) => {
-                    println!("Error: ha!{}", e); // Log the error
+                    tracing::error!(error = %e, "lost the connection to the server");
+                    error = Some(e);
                     break; // Exit the loop on error
                 }
             }
 
+            // Go away after idling for a while
+            if let Some(away_after) = self.away_after {
+                if !self.away && self.last_input.elapsed() >= away_after {
+                    self.set_away(true);
+                    self.auto_away = true;
+                }
+            }
+
+            // so the server doesn't take us for gone while we only read
+            if self.pinged.elapsed() >= HEARTBEAT {
+                self.pinged = Instant::now();
+                let _ = self.send(Packet::Ping);
+            }
+
+            // UDP doesn't tell us if nobody is listening, silence is all we get
+            if self.joining.is_some_and(|since| since.elapsed() >= JOIN_TIMEOUT) && !self.fallbacks.is_empty() {
+                self.fail_over();
+            }
+            else if self.joining.is_some_and(|since| since.elapsed() >= JOIN_TIMEOUT) {
+                self.joining = None;
+                self.notice(format!("No answer from the room at {}. The host may have closed it or the room key may be wrong. To start a room of your own, run Blossom without --roomkey.", self.connectaddr));
+            }
+
+            if self.read_receipts {
+                self.send_read();
+            }
+
+            // Typing and away updates that were held back
+            for packet in self.throttle.due(Instant::now()) {
+                let _ = self.send(packet);
+            }
+
+            // Messages the server never relayed back
+            for line in self.outbox.expired(Instant::now()) {
+                self.mark(line, " ✗".red());
+            }
+
+            // Write out joins and leaves that have settled
+            for (change, usernames) in self.ui.presence.flush(Instant::now()) {
+                self.presence_line(change, &usernames);
+            }
+
             // Draw the current state of the terminal UI
             terminal.draw(|f| self.draw(f))?;
 
@@ -175,34 +446,46 @@ impl App {
         
         // Perform a graceful shutdown of the application
 
+        // Whatever the throttle held back still goes out, then let the room know we're leaving
+        for packet in self.throttle.drain() {
+            let _ = self.send(packet);
+        }
+        let _ = self.send(Packet::Leave { username: self.ui.username.clone(), reason: LeaveReason::Quit });
+
+        // Offline there is no yggdrasil to clean up after
+        let Some(yggdr) = &mut self.yggdr else {
+            return error.map_or(Ok(()), Err);
+        };
+
         // Terminate the yggdrasil process
-        match self.yggdr.kill(){
+        match yggdr.kill(){
             Ok(_) => {},
-            Err(e) => error = Some(Error::new(ErrorKind::Other, format!("Failed to terminate yggdrasil process: {}", e)))
+            Err(e) => error = Some(Error::other(format!("Failed to terminate yggdrasil process: {}", e)))
         }
         
-        if let Some(servershutter) = &self.servershutter {
+        if let Some(servertx) = &self.servertx {
             // Send a shutdown signal to the server
-            match servershutter.send(()) {
+            match servertx.send(server::Command::Shutdown) {
                 Ok(_) => {},
-                Err(e) => error = Some(Error::new(ErrorKind::Other, format!("Failed to send shutdown signal to the server: {}", e)))
+                Err(e) => error = Some(Error::other(format!("Failed to send shutdown signal to the server: {}", e)))
             }
-            // Delete the yggdrasil address
-            match yggdrasil::del_addr(self.connectaddr.clone()) {
+            // Delete the yggdrasil address, the one in the room key even if --bind moved the server
+            let yggaddr = decode_roomkey(&self.ui.roomkey).map(|(_, addr)| addr).unwrap_or_else(|_| self.connectaddr.clone());
+            match yggdrasil::del_addr(yggaddr) {
                 Ok(_) => {},
-                Err(e) => error = Some(Error::new(ErrorKind::Other, format!("Failed to delete yggdrasil address: {}\r\n{}", e, "Start and close Blossom again to fix this.")))
+                Err(e) => error = Some(Error::other(format!("Failed to delete yggdrasil address: {}\r\n{}", e, "Start and close Blossom again to fix this.")))
             }
         }
         // Delete the configuration file        
         match yggdrasil::delconf() {
             Ok(_) => {},
-            Err(e) => error = Some(Error::new(ErrorKind::Other, format!("Failed to delete configuration file: {}", e)))
+            Err(e) => error = Some(Error::other(format!("Failed to delete configuration file: {}", e)))
         }
 
         // Delete the log file
         match yggdrasil::del_log() {
             Ok(_) => {},
-            Err(e) => error = Some(Error::new(ErrorKind::Other, format!("Failed to delete log file: {}", e)))
+            Err(e) => error = Some(Error::other(format!("Failed to delete log file: {}", e)))
         }
 
         if let Some(error) = error {
@@ -211,8 +494,308 @@ impl App {
         Ok(())
     }
 
+    /// Moves on to the next room from --servers. Whoever was in the last one
+    /// is forgotten, the new room tells us who's there once we're in.
+    fn fail_over(&mut self) {
+        let Some((roomkey, connectaddr)) = self.fallbacks.pop() else { return };
+        self.notice(format!("The room at {} isn't answering, trying {}", self.connectaddr, connectaddr));
+        tracing::info!(from = %self.connectaddr, to = %connectaddr, "failing over");
+        self.reconnects += 1;
+        self.reconnect_hidden = false;
+        self.ui.roomkey = roomkey;
+        self.connectaddr = connectaddr;
+        self.ui.roomusers = Roster::default();
+        self.ui.topic.clear();
+        self.joining = Some(Instant::now());
+        match self.transport.connect(&self.connectaddr) {
+            Ok(()) => {
+                self.localaddr = self.transport.local_addr().ok();
+                let _ = self.send(Packet::Join(self.ui.username.clone()));
+            }
+            // counts as no answer, so the next one gets tried once the join times out
+            Err(e) => self.notice(format!("Failed to connect to the room at {}: {}", self.connectaddr, e))
+        }
+    }
+
+    pub fn handle_packet(&mut self, packet: Packet) {
+        match packet {
+            Packet::Join(username) => record_join(&mut self.ui.roomusers, &mut self.ui.presence, username, Instant::now()),
+            Packet::Typing { username, typing } => {
+                if username != self.ui.username {
+                    self.ui.typing.set(&username, typing, Instant::now());
+                }
+            }
+            Packet::Chat { username, message, sent } => self.chat(username, message, None, sent),
+            Packet::Reply { username, message, to, quote, sent } => self.chat(username, message, Some((to, quote)), sent),
+            Packet::Edit { username, old, message } => self.edited(username, &old, message),
+            Packet::Unsend { username, message } => self.unsent(&username, &message),
+            Packet::Read { username, to, message } => self.read(username, &to, &message),
+            Packet::Status { username, away } => {
+                self.ui.roomusers.set_away(&username, away);
+            }
+            // the server turned our /nick down by renaming us back
+            Packet::Nick { old, new } if new == self.ui.username && self.renaming.as_ref() == Some(&old) => {
+                self.renaming = None;
+                self.notice(format!("The room didn't let you be {}, someone has that name or it's too long. You're still {}", old, new));
+            }
+            Packet::Nick { old, new } => {
+                if old == self.ui.username {
+                    self.renaming = None;
+                    self.ui.username = new.clone();
+                    // the server renames us before we're in the room if our name was taken
+                    if !self.ui.roomusers.contains(&old) {
+                        self.notice(format!("Someone in the room is already called {}, you joined as {}", old, new));
+                        return;
+                    }
+                }
+                self.ui.roomusers.rename(&old, new.clone());
+                self.ui.typing.rename(&old, &new);
+                // what they said under the old name is still theirs, to reply to or for us to edit
+                for said in self.said.iter_mut().filter(|said| said.username == old) {
+                    said.username = new.clone();
+                }
+                if let Some((by, _)) = self.pinned.as_mut().filter(|(by, _)| *by == old) {
+                    *by = new.clone();
+                }
+                self.push_history(Line::from(vec![old.red(), " is now ".red(), new.red()]));
+            }
+            Packet::Topic(topic) => {
+                // joining brings the current topic too, which is worth seeing in full once
+                self.notice(if topic.is_empty() { "The topic was cleared".to_string() } else { format!("Topic: {}", topic) });
+                self.ui.topic = topic;
+            }
+            Packet::Ping => {}
+            Packet::Private { username, to, message } => self.private(username, &to, message),
+            // the server lost track of us while our packets didn't get through
+            Packet::Leave { username, reason: LeaveReason::Timeout } if username == self.ui.username => {
+                self.notice("The room hadn't heard from you in a while, joining again".to_string());
+                self.ui.roomusers = Roster::default();
+                self.joining = Some(Instant::now());
+                let _ = self.send(Packet::Join(self.ui.username.clone()));
+            }
+            // the host doesn't want us back, so we don't try
+            Packet::Leave { username, reason: LeaveReason::Kicked } if username == self.ui.username => {
+                self.kicked = true;
+                self.joining = None;
+                self.ui.roomusers = Roster::default();
+                self.ui.typing = Typing::default();
+                self.push_history(Line::from("You were kicked from the room".light_red().bold()));
+            }
+            Packet::Leave { username, reason } => {
+                // a repeated leave, nobody new is gone
+                if !self.ui.roomusers.leave(&username) {
+                    return;
+                }
+                self.ui.typing.set(&username, false, Instant::now());
+                if reason == LeaveReason::Kicked {
+                    // kicks are rare and worth seeing right away
+                    self.presence_line(Change::Left(reason), &[username]);
+                } else {
+                    self.ui.presence.push(Change::Left(reason), username, Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Shows a message from the room. `sent` is when the sender says they
+    /// sent it.
+    fn chat(&mut self, username: String, message: String, reply: Option<(String, String)>, sent: u64) {
+        // a message is the end of typing it, even if the packet saying so got lost
+        self.ui.typing.set(&username, false, Instant::now());
+
+        // our own message coming back means the server got it
+        if username == self.ui.username {
+            let kind = reply.clone().map_or(delivery::Kind::Chat, |(to, quote)| delivery::Kind::Reply(to, quote));
+            if let Some((line, sent)) = self.outbox.echoed(&kind, &message) {
+                self.stats.rtt = Some(sent.elapsed());
+                return self.mark(line, " ✓".green());
+            }
+        }
+
+        // ENCRYPTION IS DEPRECATED
+        // Decrypt the received message
+        //let decrypted =crypt::decrypt(&self.cipher, buffer[..size].as_ref()).unwrap_or("Failed to decrypt this message".to_string());
+
+        // Filtered words are hidden from what we show, never from what's sent
+        let message = if username == self.ui.username { message } else { mask(&message, &self.ui.filter.find(&message)) };
+
+        // Highlight keywords in messages from others, and ring the bell if this room asks for it
+        let hits = if username == self.ui.username { Vec::new() } else { self.ui.keywords.find(&message) };
+        let ring = match self.notify_level() {
+            Level::All => username != self.ui.username,
+            Level::Mentions => !hits.is_empty() && self.ui.keywords.bell,
+            Level::Muted => false,
+        };
+        if ring && !self.ui.dnd {
+            ring_bell();
+        }
+
+        // Add the message to the chat history
+        self.separate(&username);
+        if let Some((to, quote)) = reply {
+            self.push_quote(&to, &quote);
+        }
+        let received = Local::now();
+        let (at, skewed) = written(sent, received);
+        if (received - at).num_seconds() > 0 {
+            tracing::debug!("message from {} written at {}, received at {}", username, at, received);
+        }
+        let spans = highlight(&message, &hits);
+        self.push_chat(username, &message, spans, at, skewed);
+    }
+
+    /// A message for some of the room only, us among them.
+    fn private(&mut self, username: String, to: &[String], message: String) {
+        self.ui.typing.set(&username, false, Instant::now());
+        // nothing but a well-behaved server keeps these from reaching everyone
+        if username != self.ui.username && !to.contains(&self.ui.username) {
+            return;
+        }
+        if username == self.ui.username {
+            if let Some((line, sent)) = self.outbox.echoed(&delivery::Kind::Private(to.to_vec()), &message) {
+                self.stats.rtt = Some(sent.elapsed());
+                return self.mark(line, " ✓".green());
+            }
+        }
+        let message = if username == self.ui.username { message } else { mask(&message, &self.ui.filter.find(&message)) };
+        let hits = if username == self.ui.username { Vec::new() } else { self.ui.keywords.find(&message) };
+        // it's meant for us, like a mention
+        if username != self.ui.username && self.notify_level() != Level::Muted && !self.ui.dnd {
+            ring_bell();
+        }
+        self.separate(&username);
+        let mut spans = vec![private_mark(to)];
+        spans.extend(highlight(&message, &hits));
+        self.push_private(username, &message, spans);
+    }
+
+    /// Adds a private message to the history. It's left out of what can be
+    /// replied to, edited, pinned or marked as read, all of which would
+    /// reach the whole room.
+    fn push_private(&mut self, username: String, text: &str, message: Vec<Span<'static>>) {
+        self.push_chat(username, text, message, Local::now(), false);
+        self.said.pop();
+    }
+
+    /// The newest message in the history from `username` that reads `text`.
+    /// What we show of others' messages is masked, so that's what has to match.
+    fn find_said(&self, username: &str, text: &str) -> Option<usize> {
+        let text = if username == self.ui.username { text.to_string() } else { mask(text, &self.ui.filter.find(text)) };
+        self.said.iter().rposition(|said| said.username == username && said.text == text)
+    }
+
+    /// Rewrites a message in the history, if it's still there.
+    fn edited(&mut self, username: String, old: &str, message: String) {
+        let own = username == self.ui.username;
+        let Some(index) = self.find_said(&username, old) else { return };
+        let message = if own { message } else { mask(&message, &self.ui.filter.find(&message)) };
+        let hits = if own { Vec::new() } else { self.ui.keywords.find(&message) };
+        let mut spans = highlight(&message, &hits);
+        spans.push(" (edited)".dark_gray().italic());
+        self.replace_said(index, spans);
+        if self.pinned.as_ref().is_some_and(|(by, text)| *by == username && text == old) {
+            self.pinned = Some((username, message.clone()));
+        }
+        self.said[index].text = message;
+    }
+
+    /// Notes under our message that someone has seen it.
+    fn read(&mut self, username: String, to: &str, message: &str) {
+        if to != self.ui.username || username == self.ui.username {
+            return;
+        }
+        let Some(index) = self.find_said(to, message) else { return };
+        let said = &mut self.said[index];
+        if said.read_by.contains(&username) {
+            return;
+        }
+        said.read_by.push(username);
+        let (last, mark) = (said.line + said.lines - 1, format!(" ✓ read by {}", presence::summarize(&said.read_by)));
+        self.mark(last, mark.green());
+    }
+
+    /// Tells the room we've seen the newest message from someone else, once
+    /// it's at the bottom of the history where we can see it. Not while we
+    /// don't want to be disturbed, the view isn't following then.
+    fn send_read(&mut self) {
+        if self.ui.dnd || self.ui.scroll.get() > 0 {
+            return;
+        }
+        let Some(said) = self.said.iter().rev().find(|said| said.username != self.ui.username) else { return };
+        if self.read_sent == Some(said.line) {
+            return;
+        }
+        self.read_sent = Some(said.line);
+        let packet = Packet::Read { username: self.ui.username.clone(), to: said.username.clone(), message: said.text.clone() };
+        let _ = self.control(packet);
+    }
+
+    /// Leaves a note where a message was taken back. It can't be replied to
+    /// or edited anymore.
+    fn unsent(&mut self, username: &str, message: &str) {
+        let Some(index) = self.find_said(username, message) else { return };
+        self.replace_said(index, vec!["message deleted".dark_gray().italic()]);
+        if self.pinned.as_ref().is_some_and(|(by, text)| by == username && text == message) {
+            self.pinned = None;
+        }
+        if self.selected == Some(self.said[index].line) {
+            self.select(None);
+        }
+        self.said.remove(index);
+    }
+
+    /// Swaps the text of a message in the history for `spans`, moving the
+    /// lines after it if it now takes more or fewer.
+    fn replace_said(&mut self, index: usize, mut spans: Vec<Span<'static>>) {
+        let (start, count) = (self.said[index].line, self.said[index].lines);
+        let Some(first) = start.checked_sub(self.ui.trimmed).and_then(|line| self.ui.history.get(line)) else { return };
+
+        // the name in front stays as it was, and so does the delivery mark on our own messages
+        let prefix = first.spans.iter().take(self.said[index].prefix).cloned().collect();
+        if self.said[index].username == self.ui.username {
+            let last = start + count - 1 - self.ui.trimmed;
+            spans.extend(self.ui.history[last].spans.last().cloned());
+        }
+        let lines = message_lines(prefix, spans, self.compact_newlines);
+
+        let delta = lines.len() as isize - count as isize;
+        let from = start - self.ui.trimmed;
+        self.ui.history.splice(from..from + count, lines);
+        self.said[index].lines = (count as isize + delta) as usize;
+        for (line, _) in self.ui.stamps.iter_mut().filter(|(line, _)| *line > start) {
+            *line = line.saturating_add_signed(delta);
+        }
+        for said in self.said.iter_mut().filter(|said| said.line > start) {
+            said.line = said.line.saturating_add_signed(delta);
+        }
+        if let Some(selected) = self.selected.as_mut().filter(|selected| **selected > start) {
+            *selected = selected.saturating_add_signed(delta);
+        }
+        self.outbox.shift(start, delta);
+    }
+
+    fn presence_line(&mut self, change: Change, usernames: &[String]) {
+        // kicks still show, they are rare and someone meant them
+        if self.quiet && change != Change::Left(LeaveReason::Kicked) {
+            return;
+        }
+        let names = presence::summarize(usernames);
+        let time = chrono::Local::now().format(&self.time_format).to_string();
+        // a clean quit looks like a join, everything else stands out a bit more
+        let line = match change {
+            Change::Joined => Line::from(vec![names.red(), " joined the room".red()]),
+            Change::Left(LeaveReason::Quit) => Line::from(vec![format!("{} ", time).dark_gray(), names.red(), " left the room".red()]),
+            Change::Left(LeaveReason::Timeout) => Line::from(vec![format!("{} ", time).dark_gray(), names.magenta(), " left the room (timed out)".magenta().italic()]),
+            Change::Left(LeaveReason::Kicked) => Line::from(vec![format!("{} ", time).dark_gray(), names.light_red().bold(), " was kicked from the room".light_red().bold()]),
+        };
+        self.push_history(line);
+    }
+
     fn draw(&self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
+        if let Some(position) = self.ui.cursor.get() {
+            frame.set_cursor_position(position);
+        }
     }
 
     fn handle_events(&mut self) -> io::Result<()> {
@@ -231,41 +814,609 @@ impl App {
         Ok(())
     }
 
-    fn handle_key_event(&mut self, key_event: KeyEvent) {
-        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
-            if let KeyCode::Char('c') = key_event.code {
-                self.exit();
+    /// What a key press does, `run` hands every one to this.
+    pub fn handle_key_event(&mut self, key_event: KeyEvent) {
+        self.last_input = Instant::now();
+        self.input_error = None;
+        if self.auto_away {
+            self.set_away(false);
+        }
+
+        // looking back over a long draft. Anything else brings the cursor back into view
+        match key_event.code {
+            KeyCode::Up if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.ui.input_scroll.set(self.ui.input_scroll.get() + 1);
+                return
+            }
+            KeyCode::Down if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.ui.input_scroll.set(self.ui.input_scroll.get().saturating_sub(1));
+                return
             }
-            // this will be used if we want to handle more keybinds
-            /*match key_event.code {
-                KeyCode::Char('c') => self.exit(),
+            _ => self.ui.input_scroll.set(0)
+        }
+
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            match key_event.code {
+                KeyCode::Char('c') => self.quit(),
+                KeyCode::End => self.jump_to_bottom(),
+                // what we sent before, like in a shell
+                KeyCode::Char('p') => {
+                    if let Some(line) = self.sent.older() {
+                        self.ui.input = line.to_string();
+                    }
+                }
+                KeyCode::Char('n') => self.ui.input = self.sent.newer().unwrap_or_default().to_string(),
+                // trade history rows for input rows and back
+                KeyCode::Up if self.ui.history_size.get().1 > 1 => self.input_rows += 1,
+                KeyCode::Down => self.input_rows = self.input_rows.saturating_sub(1).max(1),
+                // many terminals send Ctrl+Enter as Ctrl+J
+                KeyCode::Enter | KeyCode::Char('j') if self.send_on_ctrl_enter => {
+                    self.submit();
+                    self.update_typing();
+                }
                 _ => {}
-            }*/
+            }
             return
         }
         
         match key_event.code {
-            KeyCode::F(1) => self.ui.showusers = !self.ui.showusers,
-            KeyCode::F(2) => self.ui.showkey = !self.ui.showkey,
-            KeyCode::Enter => {
-                // Sending a message
-                
-                // if the input is empty, do nothing
-                if self.ui.input.is_empty() { return; }
-
-                // ENCRYPTION IS DEPRECATED
-                //let encrypted = crypt::encrypt(&self.cipher, self.ui.username.clone() + "|" + &self.ui.input);
-                //self.socket.send(&encrypted).unwrap();
-
-                self.socket.send(format!("{}|{}", self.ui.username, self.ui.input).as_bytes()).unwrap();
-                self.ui.input.clear();
-            },
+            KeyCode::F(1) => {
+                self.ui.showusers = !self.ui.showusers;
+                self.remember("show_users", self.ui.showusers);
+            }
+            KeyCode::F(2) => {
+                self.ui.showkey = !self.ui.showkey;
+                self.remember("show_key", self.ui.showkey);
+            }
+            KeyCode::F(3) => self.ui.showaddr = !self.ui.showaddr,
+            KeyCode::F(4) => self.toggle_dnd(),
+            KeyCode::F(5) => self.toggle_density(),
+            // a new line in the message instead of sending it
+            KeyCode::Enter if key_event.modifiers.contains(KeyModifiers::ALT) || self.send_on_ctrl_enter => self.ui.input.push('\n'),
+            // pick a message to reply to
+            KeyCode::Up if key_event.modifiers.contains(KeyModifiers::ALT) => {
+                let line = self.said.iter().rev().map(|said| said.line).find(|line| self.selected.is_none_or(|selected| *line < selected));
+                self.select(line.or(self.selected));
+            }
+            KeyCode::Down if key_event.modifiers.contains(KeyModifiers::ALT) => {
+                let line = self.selected.and_then(|selected| self.said.iter().map(|said| said.line).find(|line| *line > selected));
+                self.select(line);
+            }
+            // nothing to cancel, so it must be the banner that's in the way
+            KeyCode::Esc if self.selected.is_none() => self.reconnect_hidden = true,
+            KeyCode::Esc => self.select(None),
+            // pull our last message back up to fix it
+            KeyCode::Up if self.ui.input.is_empty() => {
+                if let Some(said) = self.said.iter().rev().find(|said| said.username == self.ui.username) {
+                    self.ui.input = format!("/edit {}", said.text);
+                }
+            }
+            KeyCode::Enter => self.submit(),
+            KeyCode::PageUp => {
+                // stop at the oldest row as the history was last drawn
+                let (width, height) = self.ui.history_size.get();
+                let rows: usize = self.ui.history.iter().map(|line| wrap::row_count(line, width)).sum();
+                self.ui.scroll.set((self.ui.scroll.get() + self.page.rows(height)).min(rows.saturating_sub(height)));
+            }
+            KeyCode::PageDown => {
+                let (_, height) = self.ui.history_size.get();
+                self.ui.scroll.set(self.ui.scroll.get().saturating_sub(self.page.rows(height)));
+                if self.ui.scroll.get() == 0 {
+                    self.ui.unread = 0;
+                }
+            }
+            KeyCode::End => self.jump_to_bottom(),
             KeyCode::Backspace => {
                 self.ui.input.pop();
             },
             KeyCode::Char(c) => self.ui.input.push(c),
             _ => {}
         }
+        self.update_typing();
+    }
+
+    /// Sends what's typed in, or runs it if it's a command.
+    fn submit(&mut self) {
+        // if the input is empty, there's nothing to send
+        if self.ui.input.is_empty() {
+            self.input_error = Some(Instant::now());
+            return;
+        }
+
+        self.sent.push(&self.ui.input);
+        self.save_sent();
+
+        // commands are handled locally and never sent
+        if let Some(command) = self.ui.input.strip_prefix('/') {
+            let command = command.to_owned();
+            self.handle_command(&command);
+            self.ui.input.clear();
+            return;
+        }
+
+        // ENCRYPTION IS DEPRECATED
+        //let encrypted = crypt::encrypt(&self.cipher, self.ui.username.clone() + "|" + &self.ui.input);
+        //self.transport.send(&encrypted).unwrap();
+
+        let message = emoji::expand(&self.ui.input);
+        let reply = self.selected.and_then(|line| self.said.iter().find(|said| said.line == line)).map(|said| (said.username.clone(), quote(&said.text)));
+        let packet = match reply.clone() {
+            Some((to, quote)) => Packet::Reply { username: self.ui.username.clone(), message: message.clone(), to, quote, sent: protocol::now() },
+            None => Packet::Chat { username: self.ui.username.clone(), message: message.clone(), sent: protocol::now() }
+        };
+        if let Err(e) = self.send(packet) {
+            // keep the input so it can be sent again
+            return self.notice(format!("Failed to send the message: {}", e));
+        }
+        self.ui.input.clear();
+        self.select(None);
+
+        // show it right away, marked as sent until the server relays it back
+        self.separate(&self.ui.username.clone());
+        let kind = reply.clone().map_or(delivery::Kind::Chat, |(to, quote)| delivery::Kind::Reply(to, quote));
+        if let Some((to, quote)) = reply {
+            self.push_quote(&to, &quote);
+        }
+        let mut line = highlight(&message, &[]);
+        line.push(" ◷".dark_gray());
+        self.push_chat(self.ui.username.clone(), &message, line, Local::now(), false);
+        self.outbox.sent(self.ui.trimmed + self.ui.history.len() - 1, kind, message, Instant::now());
+    }
+
+    /// Tells the room when we start or stop typing a message, commands don't
+    /// count. While typing it's repeated now and then, so others know we
+    /// haven't just vanished.
+    fn update_typing(&mut self) {
+        let typing = !self.ui.input.is_empty() && !self.ui.input.starts_with('/');
+        let due = match self.typing_sent {
+            Some(sent) => !typing || sent.elapsed() >= self.typing_refresh,
+            None => typing
+        };
+        if due {
+            let _ = self.control(Packet::Typing { username: self.ui.username.clone(), typing });
+            self.typing_sent = if typing { Some(Instant::now()) } else { None };
+        }
+    }
+
+    fn handle_command(&mut self, command: &str) {
+        let mut args = command.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("notify"), Some("add")) => {
+                for word in args {
+                    if self.ui.keywords.add(word) {
+                        self.notice(format!("Added keyword \"{}\"", word));
+                    }
+                }
+            }
+            (Some("notify"), Some("del")) => {
+                for word in args {
+                    if !self.ui.keywords.remove(word) {
+                        self.notice(format!("\"{}\" is not a keyword", word));
+                    }
+                }
+            }
+            (Some("notify"), Some("list")) => {
+                if self.ui.keywords.words.is_empty() {
+                    self.notice("No keywords set".to_string());
+                } else {
+                    self.notice(format!("Keywords: {}", self.ui.keywords.words.join(", ")));
+                }
+            }
+            (Some("notify"), Some("mode")) => match args.next() {
+                Some("word") => self.ui.keywords.substring = false,
+                Some("substring") => self.ui.keywords.substring = true,
+                _ => self.refuse("Usage: /notify mode word|substring".to_string())
+            },
+            (Some("notify"), Some("bell")) => match args.next() {
+                Some("on") => self.ui.keywords.bell = true,
+                Some("off") => self.ui.keywords.bell = false,
+                _ => self.refuse("Usage: /notify bell on|off".to_string())
+            },
+            (Some("notify"), Some(level)) if Level::parse(level).is_some() => {
+                let level = Level::parse(level).unwrap();
+                self.notify_rooms.retain(|(key, _)| *key != self.ui.roomkey);
+                self.notify_rooms.push((self.ui.roomkey.clone(), level));
+                match Config::save("notify_rooms", &notify::format_rooms(&self.notify_rooms)) {
+                    Ok(()) => self.notice(format!("Notifications for this room: {}", level.name())),
+                    Err(e) => self.notice(format!("Notifications for this room: {}, until Blossom closes. Failed to save it: {}", level.name(), e))
+                }
+            }
+            (Some("notify"), _) => self.refuse("Usage: /notify add|del|list|mode|bell, or /notify all|mentions|muted for this room".to_string()),
+            (Some(action @ ("kick" | "ban")), Some(name)) => {
+                // only the host runs the server, so only the host can enforce this
+                let servertx = match &self.servertx {
+                    Some(servertx) => servertx,
+                    None => return self.notice(format!("Only the host of the room can {} users", action))
+                };
+                if !self.ui.roomusers.contains(name) {
+                    return self.refuse(format!("No user named \"{}\" in the room", name));
+                }
+                let command = if action == "kick" { server::Command::Kick(name.to_string()) } else { server::Command::Ban(name.to_string()) };
+                if servertx.send(command).is_err() {
+                    self.notice("The server is not running".to_string());
+                }
+                else if action == "ban" {
+                    self.notice(format!("Bans go by address, anyone else connecting from where {} does is kept out too", name));
+                }
+            }
+            (Some(action @ ("kick" | "ban")), None) => self.refuse(format!("Usage: /{} <user>", action)),
+            (Some("topic"), first) => {
+                let servertx = match &self.servertx {
+                    Some(servertx) => servertx,
+                    None => return self.notice("Only the host of the room can change the topic".to_string())
+                };
+                // everything after the command, spaces included
+                let topic = match first {
+                    Some(_) => command["topic".len()..].trim().to_string(),
+                    None => String::new()
+                };
+                if servertx.send(server::Command::Topic(topic)).is_err() {
+                    self.notice("The server is not running".to_string());
+                }
+            }
+            (Some("nick"), Some(name)) => {
+                if name.contains('|') {
+                    self.refuse("Usernames can't contain \"|\"".to_string());
+                } else if name.len() > MAX_USERNAME {
+                    self.refuse(format!("Usernames can be {} bytes long at most", MAX_USERNAME));
+                } else if self.ui.roomusers.contains(name) {
+                    self.refuse(format!("\"{}\" is already taken", name));
+                } else {
+                    // our name changes once the server confirms it
+                    if self.send(Packet::Nick { old: self.ui.username.clone(), new: name.to_string() }).is_ok() {
+                        self.renaming = Some(name.to_string());
+                    }
+                }
+            }
+            (Some("edit"), Some(_)) => {
+                let Some(said) = self.said.iter().rev().find(|said| said.username == self.ui.username) else {
+                    return self.notice("You haven't sent anything that can be edited".to_string());
+                };
+                // everything after the command, line breaks included
+                let message = emoji::expand(command["edit".len()..].trim());
+                if message == said.text {
+                    return;
+                }
+                let packet = Packet::Edit { username: self.ui.username.clone(), old: said.text.clone(), message };
+                // the history changes once the server relays it back, like for everyone else
+                if let Err(e) = self.send(packet) {
+                    self.notice(format!("Failed to send the edit: {}", e));
+                }
+            }
+            (Some("unsend"), _) => {
+                let Some(said) = self.said.iter().rev().find(|said| said.username == self.ui.username) else {
+                    return self.notice("You haven't sent anything that can be taken back".to_string());
+                };
+                let packet = Packet::Unsend { username: self.ui.username.clone(), message: said.text.clone() };
+                if let Err(e) = self.send(packet) {
+                    self.notice(format!("Failed to take the message back: {}", e));
+                }
+            }
+            (Some("edit"), None) => self.refuse("Usage: /edit <message>, or Up to edit your last message".to_string()),
+            (Some("nick"), None) => self.refuse("Usage: /nick <name>".to_string()),
+            (Some("pin"), _) => match self.selected.and_then(|line| self.said.iter().find(|said| said.line == line)) {
+                Some(said) => {
+                    self.pinned = Some((said.username.clone(), said.text.clone()));
+                    self.select(None);
+                }
+                None => self.refuse("Pick a message with Alt+Up first, then /pin keeps it above the history".to_string())
+            },
+            (Some("unpin"), _) => self.pinned = None,
+            (Some("quiet"), _) => {
+                self.quiet = !self.quiet;
+                self.notice(if self.quiet { "Joins and leaves are hidden".to_string() } else { "Joins and leaves are shown again".to_string() });
+            }
+            (Some("history"), Some("clear")) => {
+                self.sent.clear();
+                self.save_sent();
+                self.notice("Forgot everything you sent".to_string());
+            }
+            (Some("history"), _) => self.refuse("Usage: /history clear".to_string()),
+            (Some("dnd"), _) => self.toggle_dnd(),
+            (Some("clear"), _) => self.clear(),
+            (Some("theme"), Some("preview")) => match args.next().and_then(Theme::parse) {
+                Some(theme) => self.preview = Some((theme, Instant::now())),
+                None => self.refuse("Usage: /theme preview default|high-contrast".to_string())
+            },
+            (Some("theme"), _) => self.notice(format!("The theme is {}. /theme preview <name> shows another one for a moment, set theme in the config to keep it", self.theme.name())),
+            (Some("away"), _) => {
+                let away = !self.away;
+                self.set_away(away);
+                self.notice(if away { "You are now away".to_string() } else { "You are back".to_string() });
+            }
+            (Some("msg"), Some(targets)) => {
+                // everything after the names, line breaks included
+                let message = emoji::expand(command.trim_start()["msg".len()..].trim_start()[targets.len()..].trim());
+                if message.is_empty() {
+                    return self.refuse("Usage: /msg <user|@group>,... <message>. Only the host decides who gets it, nothing is encrypted".to_string());
+                }
+                let to = match groups::expand(targets, &self.groups) {
+                    Ok(to) if !to.is_empty() => to,
+                    Ok(_) => return self.refuse("Usage: /msg <user|@group>,... <message>. Only the host decides who gets it, nothing is encrypted".to_string()),
+                    Err(group) => return self.refuse(format!("There's no group called @{}, see /group", group))
+                };
+                // without a host, everyone listening on the group gets every packet
+                if self.connectaddr.parse::<SocketAddr>().is_ok_and(|addr| transport::is_group(addr.ip())) {
+                    return self.refuse("There's no host in this room to pass a message on to some users only, everyone on the group would see it".to_string());
+                }
+                if let Err(e) = self.send(Packet::Private { username: self.ui.username.clone(), to: to.clone(), message: message.clone() }) {
+                    return self.notice(format!("Failed to send the message: {}", e));
+                }
+                self.separate(&self.ui.username.clone());
+                let mut line = vec![private_mark(&to)];
+                line.extend(highlight(&message, &[]));
+                line.push(" ◷".dark_gray());
+                self.push_private(self.ui.username.clone(), &message, line);
+                self.outbox.sent(self.ui.trimmed + self.ui.history.len() - 1, delivery::Kind::Private(to), message, Instant::now());
+            }
+            (Some("msg"), None) => self.refuse("Usage: /msg <user|@group>,... <message>. Only the host decides who gets it, nothing is encrypted".to_string()),
+            (Some("group"), None) => match self.groups.is_empty() {
+                true => self.notice("No groups yet, /group <name> <user>... makes one".to_string()),
+                false => self.notice(format!("Groups: {}", groups::format(&self.groups)))
+            },
+            (Some("group"), Some(name)) => {
+                let members: Vec<String> = args.map(String::from).collect();
+                if [name].into_iter().chain(members.iter().map(String::as_str)).any(|word| word.contains([',', ':'])) {
+                    return self.refuse("Names in groups can't have , or : in them".to_string());
+                }
+                self.groups.retain(|(group, _)| group != name);
+                let done = if members.is_empty() {
+                    format!("Group @{} is gone", name)
+                } else {
+                    let done = format!("Group @{}: {}", name, members.join(", "));
+                    self.groups.push((name.to_string(), members));
+                    done
+                };
+                match Config::save("groups", &groups::format(&self.groups)) {
+                    Ok(()) => self.notice(done),
+                    Err(e) => self.notice(format!("{}, until Blossom closes. Failed to save it: {}", done, e))
+                }
+            }
+            (Some("stats"), _) => self.ui.showstats = !self.ui.showstats,
+            (Some("who"), _) => {
+                let names: Vec<String> = self.ui.roomusers.iter().map(|user| {
+                    if user.away { format!("{} (away)", user.name) } else { user.name.clone() }
+                }).collect();
+                let count = if names.len() == 1 { "1 user".to_string() } else { format!("{} users", names.len()) };
+                self.notice(format!("{} in the room: {}", count, names.join(", ")));
+            }
+            (Some("whereami"), _) => {
+                let address = self.address();
+                self.notice(address);
+            }
+            _ => self.refuse(format!("Unknown command: /{}", command))
+        }
+    }
+
+    /// Keeps what we sent for the next run, with `save_history` on.
+    fn save_sent(&mut self) {
+        if let Some(Err(e)) = self.sent_path.as_ref().map(|path| self.sent.save(path)) {
+            tracing::warn!(error = %e, "failed to save the sent history");
+        }
+    }
+
+    /// Which messages ring the bell in the room we're in.
+    fn notify_level(&self) -> Level {
+        self.notify_rooms.iter().find(|(key, _)| *key == self.ui.roomkey).map_or(self.notify_level, |(_, level)| *level)
+    }
+
+    /// Tells the room whether we're at the keyboard.
+    fn set_away(&mut self, away: bool) {
+        self.away = away;
+        self.auto_away = false;
+        let _ = self.control(Packet::Status { username: self.ui.username.clone(), away });
+    }
+
+    /// Sends a control packet, or holds it back if one of its kind went out
+    /// just now. Held back packets go out from the main loop.
+    fn control(&mut self, packet: Packet) -> io::Result<()> {
+        match self.throttle.push(packet, Instant::now()) {
+            Some(packet) => self.send(packet),
+            None => Ok(())
+        }
+    }
+
+    /// Sends a packet to the server, counting it for the stats.
+    fn send(&mut self, packet: Packet) -> io::Result<()> {
+        if self.kicked {
+            return Err(Error::new(ErrorKind::NotConnected, "you were kicked from the room"));
+        }
+        let packet = if self.compress { packet.encode_compressed() } else { packet.encode() };
+        self.transport.send(&packet)?;
+        self.stats.sent(packet.len());
+        Ok(())
+    }
+
+    /// Adds a message to the history. With the gutter on, it starts with the
+    /// sender's initial, left out when the line above is from them too. `at`
+    /// is the time shown with it, marked with a ~ if it's `skewed`.
+    fn push_chat(&mut self, username: String, text: &str, message: Vec<Span<'static>>, at: DateTime<Local>, skewed: bool) {
+        let mut line = Vec::new();
+        let time = format!("{}{}", if skewed { "~" } else { "" }, at.format(&self.time_format));
+        match self.timestamps {
+            Timestamps::Left => line.push(format!("{} ", time).dark_gray()),
+            Timestamps::Right => self.ui.stamps.push((self.ui.trimmed + self.ui.history.len(), time.clone())),
+            Timestamps::Off => {}
+        }
+        if self.gutter {
+            if self.last_sender.as_ref().is_some_and(|(sender, _)| *sender == username) {
+                line.push("  ".into());
+            } else {
+                let initial = username.chars().next().unwrap_or(' ').to_uppercase().to_string();
+                line.push(Span::from(initial).fg(user_color(&username)).bold());
+                line.push(" ".into());
+            }
+        }
+        // the whole name is in the title of the input box when replying. Ours
+        // stands out, so it's easy to see where we came in
+        let style = if username == self.ui.username { Style::new().green().bold() } else { Style::new().cyan() };
+        let name: Vec<Span<'static>> = self.message_format.iter().map(|piece| match piece {
+            Piece::Text(text) => Span::styled(text.clone(), style),
+            Piece::User => Span::styled(ellipsize(&username, self.name_width), style),
+            Piece::Time => time.clone().dark_gray(),
+        }).collect();
+        // a quick follow up goes under the message above it, without the name again
+        let merged = self.merge_senders && self.last_sender.as_ref().is_some_and(|(sender, at)| *sender == username && at.elapsed() < MERGE_WINDOW);
+        if merged {
+            line.push(Span::raw(" ".repeat(Line::from(name).width())));
+        } else {
+            line.extend(name);
+        }
+
+        let prefix = line.len();
+        let lines = message_lines(line, message, self.compact_newlines);
+        self.said.push(Said { line: self.ui.trimmed + self.ui.history.len(), username: username.clone(), text: text.to_string(), lines: lines.len(), prefix, read_by: Vec::new() });
+        for line in lines {
+            self.push_history(line);
+        }
+        self.last_sender = Some((username.clone(), Instant::now()));
+        self.last_chat = Some((username, Instant::now()));
+    }
+
+    /// Sets a message from `username` apart from the one before it, if it's
+    /// from someone else or came long after it.
+    fn separate(&mut self, username: &str) {
+        let apart = self.last_chat.as_ref().is_some_and(|(sender, at)| sender != username || self.separator_gap.is_some_and(|gap| at.elapsed() >= gap));
+        match self.separators {
+            Separator::Line if apart => self.push_history(Line::from("────").dark_gray()),
+            Separator::Blank if apart => self.push_history(Line::default()),
+            _ => {}
+        }
+    }
+
+    /// Shows what a reply is replying to, above the reply.
+    fn push_quote(&mut self, to: &str, quote: &str) {
+        self.push_history(Line::from(vec!["  ┆ ".dark_gray(), format!("{}: {}", to, quote).dark_gray().italic()]));
+    }
+
+    /// Moves the reply selection to the message starting at `line`, taking
+    /// the highlight with it.
+    fn select(&mut self, line: Option<usize>) {
+        for (line, style) in [(self.selected, Style::default()), (line, Style::new().reversed())] {
+            if let Some(line) = line.and_then(|line| line.checked_sub(self.ui.trimmed)).and_then(|line| self.ui.history.get_mut(line)) {
+                line.style = style;
+            }
+        }
+        self.selected = line;
+    }
+
+    /// Keeps a panel shown or hidden for the next run. Only a failure is
+    /// worth a line in the history, the panel itself shows it worked.
+    fn remember(&mut self, key: &str, shown: bool) {
+        if let Err(e) = Config::save(key, &shown.to_string()) {
+            self.notice(format!("Failed to save {}: {}", key, e));
+        }
+    }
+
+    /// Switches to the other layout preset, compact to begin with. Lines
+    /// already in the history keep the layout they were made with. The
+    /// preset replaces the config's own values for the settings it's made of.
+    fn toggle_density(&mut self) {
+        let density = match self.density {
+            Some(Density::Compact) => Density::Comfortable,
+            _ => Density::Compact
+        };
+        let mut preset = Config::default();
+        density.apply(&mut preset);
+        self.separators = preset.separators;
+        self.timestamps = preset.timestamps;
+        self.merge_senders = preset.merge_senders;
+        self.compact_newlines = preset.compact_newlines;
+        self.gutter = preset.gutter;
+        self.density = Some(density);
+        match Config::save("density", density.name()) {
+            Ok(()) => self.notice(format!("Layout: {}, from the next message on. It replaces your separators, timestamps, merge_senders, newlines and gutter settings until density is removed from the config", density.name())),
+            Err(e) => self.notice(format!("Layout: {}, until Blossom closes. Failed to save it: {}", density.name(), e))
+        }
+    }
+
+    /// Turns do not disturb on or off. Coming back shows what came in meanwhile.
+    fn toggle_dnd(&mut self) {
+        self.ui.dnd = !self.ui.dnd;
+        if !self.ui.dnd {
+            self.jump_to_bottom();
+        }
+    }
+
+    /// Appends to the history, forgetting the oldest lines beyond the limit.
+    /// When `auto_scroll` holds the view, or we don't want to be disturbed,
+    /// it stays where it is and the line is counted as unread until we're
+    /// back at the bottom.
+    fn push_history(&mut self, line: Line<'static>) {
+        let scroll = self.ui.scroll.get();
+        if self.ui.dnd || self.auto_scroll.holds(scroll) {
+            self.ui.scroll.set(scroll + wrap::row_count(&line, self.ui.history_size.get().0));
+            self.ui.unread += 1;
+        } else {
+            self.jump_to_bottom();
+        }
+        self.ui.history.push(line);
+        self.last_sender = None;
+        if self.ui.history.len() > self.history_limit {
+            let excess = self.ui.history.len() - self.history_limit;
+            self.ui.history.drain(..excess);
+            self.ui.trimmed += excess;
+            let trimmed = self.ui.trimmed;
+            self.said.retain(|said| said.line >= trimmed);
+            self.ui.stamps.retain(|(line, _)| *line >= trimmed);
+            if self.selected.is_some_and(|line| line < trimmed) {
+                self.selected = None;
+            }
+        }
+    }
+
+    /// Replaces the delivery mark at the end of one of our messages, unless the
+    /// line has been dropped from the history by now.
+    fn mark(&mut self, line: usize, mark: Span<'static>) {
+        let line = line.checked_sub(self.ui.trimmed).and_then(|line| self.ui.history.get_mut(line));
+        if let Some(last) = line.and_then(|line| line.spans.last_mut()) {
+            *last = mark;
+        }
+    }
+
+    /// Empties the history. Lines keep their numbers, so anything still
+    /// pointing at a cleared line just finds nothing there.
+    fn clear(&mut self) {
+        self.ui.trimmed += self.ui.history.len();
+        self.ui.history.clear();
+        self.said.clear();
+        self.ui.stamps.clear();
+        self.selected = None;
+        self.last_sender = None;
+        self.last_chat = None;
+        self.jump_to_bottom();
+    }
+
+    /// Goes back to following the newest messages.
+    fn jump_to_bottom(&mut self) {
+        self.ui.scroll.set(0);
+        self.ui.unread = 0;
+    }
+
+    /// Adds a local line to the history. These are never sent to the room.
+    fn notice(&mut self, text: String) {
+        self.push_history(Line::from(text).dark_gray().italic());
+    }
+
+    /// Like `notice`, for input that couldn't be used. The input box turns red
+    /// for a moment too, so it's noticed where we're looking.
+    fn refuse(&mut self, text: String) {
+        self.notice(text);
+        self.input_error = Some(Instant::now());
+    }
+
+    /// Exits on Ctrl+C, or asks for it again first if that's configured.
+    /// Messages that haven't made it to the room yet would be lost without a
+    /// word, so those always ask first.
+    fn quit(&mut self) {
+        if (!self.confirm_quit && self.outbox.is_empty()) || self.quit_pending() {
+            return self.exit();
+        }
+        self.quit_pressed = Some(Instant::now());
+    }
+
+    fn quit_pending(&self) -> bool {
+        self.quit_pressed.is_some_and(|pressed| pressed.elapsed() < QUIT_WINDOW)
     }
 
     fn exit(&mut self) {
@@ -274,49 +1425,421 @@ impl App {
 
 }
 
+/// The history lines of a message behind `prefix`. A message typed over
+/// several lines keeps them, lined up under the first.
+fn message_lines(prefix: Vec<Span<'static>>, message: Vec<Span<'static>>, compact: bool) -> Vec<Line<'static>> {
+    if compact {
+        let mut line = prefix;
+        line.extend(wrap::collapse_lines(message));
+        return vec![Line::from(line)];
+    }
+    let indent = " ".repeat(Line::from(prefix.clone()).width());
+    let mut lines = wrap::split_lines(message).into_iter();
+    let mut first = prefix;
+    first.extend(lines.next().unwrap_or_default());
+    std::iter::once(Line::from(first)).chain(lines.map(|rest| {
+        let mut line = vec![Span::raw(indent.clone())];
+        line.extend(rest);
+        Line::from(line)
+    })).collect()
+}
+
+/// What the history is titled: the topic, or Blossom without one, and how
+/// many are in the room once someone is.
+pub fn history_title(topic: &str, users: usize) -> String {
+    let name = if topic.is_empty() { "Blossom" } else { topic };
+    match users {
+        0 => format!(" {} ", name),
+        users => format!(" {} ({}) ", name, users)
+    }
+}
+
+/// When a message was written, going by the sender's clock. A time ahead of
+/// ours, or further behind than a message ever takes to get here, means
+/// that clock is off. Then the time it came in is used instead, and the
+/// second value says so.
+pub fn written(sent: u64, received: DateTime<Local>) -> (DateTime<Local>, bool) {
+    let behind = i64::try_from(sent).map(|sent| received.timestamp_millis() - sent);
+    match behind {
+        // clocks a moment apart aren't worth flagging
+        Ok(behind) if behind >= -(CLOCK_SLACK.as_millis() as i64) && behind <= MAX_DELAY.as_millis() as i64 => (received - chrono::Duration::milliseconds(behind.max(0)), false),
+        _ => (received, true)
+    }
+}
+
+/// Goes in front of a private message, saying who it's for.
+fn private_mark(to: &[String]) -> Span<'static> {
+    format!("→ {} ", to.join(", ")).magenta().italic()
+}
+
+/// Adds the new user to the room users list, the history line comes with the
+/// next flush. Someone already in the list is left alone: a join can be
+/// repeated by UDP or by reconnecting before the server noticed we were gone.
+pub fn record_join(roomusers: &mut Roster, presence: &mut Presence, username: String, now: Instant) {
+    if roomusers.join(username.clone()) {
+        presence.push(Change::Joined, username, now);
+    }
+}
+
+/// One frame of a spinner, something moving to show we haven't frozen.
+pub fn spinner(frame: usize) -> char {
+    const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+    FRAMES[frame % FRAMES.len()]
+}
+
+fn ring_bell() {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(b"\x07");
+    let _ = stdout.flush();
+}
+
+/// Cleans up a pasted room key and reads the host's address out of it.
+/// Whitespace is dropped, since keys copied from a terminal or a chat often
+/// come with a newline or get broken over lines. Returns the cleaned key
+/// along with the address.
+pub fn decode_roomkey(roomkey: &str) -> Result<(String, String), Error> {
+    let roomkey: String = roomkey.split_whitespace().collect();
+    if roomkey.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "The room key is empty"));
+    }
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("\"{}\" is not a room key, check that it was copied completely", roomkey));
+
+    let decoded = BASE64_STANDARD.decode(&roomkey).map_err(|_| invalid())?;
+    let connectaddr = match String::from_utf8(decoded) {
+        Ok(decoded) => decoded.replace("g", ""),
+        Err(_) => return Err(invalid())
+    };
+    if connectaddr.is_empty() {
+        return Err(invalid());
+    }
+    Ok((roomkey, connectaddr))
+}
+
+/// Picks the rows that fit into a history box `width` cells wide and
+/// `height` rows tall, ending `scroll_offset` rows above the newest one. Long
+/// lines are wrapped, so one message can take several rows. Scrolling past the
+/// oldest row just shows the top of the history.
+pub fn visible_lines<'a>(history: &'a [Line<'static>], width: usize, height: usize, scroll_offset: usize) -> Vec<Line<'a>> {
+    visible_rows(history, width, height, scroll_offset).into_iter().map(|(_, row)| row).collect()
+}
+
+/// Like `visible_lines`, with the index of the history line next to the
+/// first row of each line.
+pub fn visible_rows<'a>(history: &'a [Line<'static>], width: usize, height: usize, scroll_offset: usize) -> Vec<(Option<usize>, Line<'a>)> {
+    // only wrap as far back from the newest line as the window reaches
+    let needed = height + scroll_offset;
+    let mut rows: Vec<(Option<usize>, Line<'a>)> = Vec::new();
+    for (index, line) in history.iter().enumerate().rev() {
+        if rows.len() >= needed {
+            break;
+        }
+        let wrapped = wrap::wrap(line, width).into_iter().enumerate().map(|(i, row)| (if i == 0 { Some(index) } else { None }, row));
+        rows.extend(wrapped.rev());
+    }
+    rows.reverse();
+
+    let end = rows.len().saturating_sub(scroll_offset).max(height.min(rows.len()));
+    let start = end.saturating_sub(height);
+    rows.truncate(end);
+    rows.drain(..start);
+    rows
+}
+
+/// What the history box shows: the visible lines, or a note in the middle
+/// while there's nothing to show yet.
+pub fn history_view<'a>(history: &'a [Line<'static>], width: usize, height: usize, scroll_offset: usize) -> Vec<Line<'a>> {
+    if !history.is_empty() {
+        return visible_lines(history, width, height, scroll_offset);
+    }
+    let mut rows = vec![Line::default(); height.saturating_sub(1) / 2];
+    rows.push(Line::from("No messages yet").dark_gray().italic().centered());
+    rows
+}
+
+/// The pinned message on one row `width` cells wide, cut short if it's longer.
+pub fn pin_row(username: &str, text: &str, width: usize) -> Line<'static> {
+    let label = "pinned ";
+    let text = format!("{}: {}", username, text.replace('\n', " "));
+    Line::from(vec![label.yellow().bold(), ellipsize(&text, width.saturating_sub(label.len()).max(1)).into()])
+}
+
+/// How many cells a time takes in `format`, going by one of the longest
+/// times there are.
+pub fn time_width(format: &str) -> usize {
+    let longest = chrono::NaiveDate::from_ymd_opt(2000, 9, 27).and_then(|date| date.and_hms_opt(12, 59, 59)).unwrap();
+    longest.format(format).to_string().width()
+}
+
+/// Pads a history row out to `width` cells and puts the time after it, so
+/// the times line up on the right.
+pub fn stamp_row<'a>(mut row: Line<'a>, width: usize, stamp: &str) -> Line<'a> {
+    let padding = width.saturating_sub(row.width()) + 1;
+    row.spans.push(Span::raw(" ".repeat(padding)));
+    row.spans.push(stamp.to_string().dark_gray());
+    row
+}
+
+/// Wraps the input into rows `width` cells wide and keeps `max_rows` of
+/// them, ending `scroll` rows above the last one. Also returns the column and
+/// row the cursor goes to, which is on a row of its own when the last one is
+/// full and out of sight when scrolled. The last value is how far it really
+/// scrolled, never past the first row.
+pub fn input_rows(input: &str, width: usize, max_rows: usize, scroll: usize) -> (Vec<Line<'static>>, Option<(usize, usize)>, usize) {
+    let width = width.max(1);
+    let mut rows: Vec<Line<'static>> = Vec::new();
+    for line in input.split('\n') {
+        let line = Line::from(line);
+        rows.extend(wrap::wrap(&line, width).iter().map(|row| Line::from(row.to_string())));
+    }
+    let mut column = rows.last().map_or(0, |row| row.width());
+    if column >= width {
+        rows.push(Line::default());
+        column = 0;
+    }
+    let hidden = rows.len().saturating_sub(max_rows);
+    let scroll = scroll.min(hidden);
+    rows.truncate(rows.len() - scroll);
+    rows.drain(..hidden - scroll);
+    let cursor = (scroll == 0).then(|| (column, rows.len() - 1));
+    (rows, cursor, scroll)
+}
+
+/// Cuts a name down to `width` cells, ending it with "…" if anything was
+/// left out. A width of 0 leaves it whole.
+pub fn ellipsize(name: &str, width: usize) -> String {
+    if width == 0 || name.width() <= width {
+        return name.to_string();
+    }
+    let mut cut = String::new();
+    for c in name.chars() {
+        if cut.width() + c.width().unwrap_or(0) >= width {
+            break;
+        }
+        cut.push(c);
+    }
+    cut + "…"
+}
+
+/// The start of a message for quoting it in a reply: its first line, cut
+/// short if it's long.
+pub fn quote(text: &str) -> String {
+    let line = text.lines().next().unwrap_or("");
+    let mut quote: String = line.chars().take(QUOTE_LENGTH).collect();
+    if quote.len() < text.len() {
+        quote.push('…');
+    }
+    quote
+}
+
+/// Converts `scroll` from rows `old_width` cells wide to rows `new_width`
+/// wide, so the message at the bottom of the view is still there once the
+/// history has been wrapped again at the new width.
+pub fn reflow_scroll(history: &[Line], old_width: usize, new_width: usize, scroll: usize) -> usize {
+    // rows below the anchor at the old and the new width
+    let mut old_rows = 0;
+    let mut new_rows = 0;
+    for line in history.iter().rev() {
+        let old = wrap::row_count(line, old_width);
+        let new = wrap::row_count(line, new_width);
+        if old_rows + old > scroll {
+            // keep as many of the anchor's own rows below the view as still exist
+            return new_rows + (scroll - old_rows).min(new - 1);
+        }
+        old_rows += old;
+        new_rows += new;
+    }
+    new_rows
+}
+
+/// A color for the user that stays the same everywhere, picked by hashing
+/// their name.
+pub fn user_color(username: &str) -> Color {
+    const COLORS: [Color; 6] = [Color::LightRed, Color::LightGreen, Color::LightYellow, Color::LightBlue, Color::LightMagenta, Color::LightCyan];
+    // FNV-1a, std's hasher is randomly seeded so it would differ between runs
+    let hash = username.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    COLORS[(hash % COLORS.len() as u64) as usize]
+}
+
+impl App {
+    /// Where we send from and where to, for checking we're on the right interface.
+    fn address(&self) -> String {
+        match self.localaddr {
+            Some(localaddr) => format!("Bound to {}, connected to {}", localaddr, self.connectaddr),
+            None => format!("Not connected yet, connecting to {}", self.connectaddr)
+        }
+    }
+
+    fn block<'a>(&self, title: impl Into<Line<'a>>) -> Block<'a> {
+        Block::bordered().border_set(self.ui.border).title(title)
+    }
+}
+
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let block = Block::bordered().border_set(border::PLAIN);
         let style = ratatui::style::Style::default().fg(ratatui::style::Color::Cyan);
 
         let mut widthleft = area.width;
         let mut heightleft = area.height;
         
-        if self.ui.showkey {
+        // panels that don't fit a short terminal are left out
+        if self.ui.showkey && heightleft >= 3 {
             heightleft -= 3;
-            Paragraph::new(Line::from(self.ui.roomkey.clone()))
-                .block(block.to_owned().title(" Room Key "))
-                .style(style.to_owned())
+            Paragraph::new(self.ui.roomkey.as_str())
+                .block(self.block(" Room Key "))
+                .style(style)
                 .render(Rect { x: 0, y: 0, width: widthleft, height: 3 }, buf);
         }
 
+        if self.ui.showaddr && heightleft >= 3 {
+            Paragraph::new(self.address())
+                .block(self.block(" Address "))
+                .style(style)
+                .render(Rect { x: 0, y: area.height - heightleft, width: widthleft, height: 3 }, buf);
+            heightleft -= 3;
+        }
+
+        if self.ui.showstats && heightleft >= 3 {
+            Paragraph::new(self.stats.summary(self.ui.unreadable))
+                .block(self.block(" Stats "))
+                .style(style)
+                .render(Rect { x: 0, y: area.height - heightleft, width: widthleft, height: 3 }, buf);
+            heightleft -= 3;
+        }
+
         if self.ui.showusers {
             widthleft -= 20;
             let mut users = Vec::new();
             for user in self.ui.roomusers.iter() {
-                users.push(Line::from(user.clone().to_string()));
+                // what fits inside the panel's borders
+                let line = Line::from(ellipsize(&user.name, 18));
+                users.push(if user.away { line.dim() } else { line });
             }
             Paragraph::new(users)
-                .block(block.to_owned().title(" Users "))
-                .style(style.to_owned())
+                .block(self.block(" Users "))
+                .style(style)
                 .render(Rect { x: 0, y: area.height - heightleft, width: 20, height: heightleft }, buf);
         }
 
-        let mut history = Vec::new();
-        for message in &self.ui.history {
-            history.push(message.to_owned());
+        // the input box grows with what's typed, one row at least and never
+        // so far that the history is squeezed out
+        let max_rows = self.input_rows.min((heightleft as usize).saturating_sub(MIN_HISTORY_HEIGHT + 2)).max(1);
+        let (input, cursor, input_scroll) = input_rows(&self.ui.input, widthleft.saturating_sub(2) as usize, max_rows, self.ui.input_scroll.get());
+        self.ui.input_scroll.set(input_scroll);
+        let inputheight = (input.len() as u16 + 2).min(heightleft);
+        let statusheight = if self.status.is_empty() { 0 } else { 3.min(heightleft - inputheight) };
+
+        // the history box loses two rows and columns to its borders
+        // and times on the right take their own column
+        // and a pinned message takes the top row
+        let stamps_width = if self.timestamps == Timestamps::Right { self.stamp_width } else { 0 };
+        let pinned_height = if self.pinned.is_some() { 1 } else { 0 };
+        let history_size = ((widthleft.saturating_sub(2) as usize).saturating_sub(stamps_width), heightleft.saturating_sub(inputheight + statusheight + 2 + pinned_height) as usize);
+        let (old_width, _) = self.ui.history_size.replace(history_size);
+        // resizing rewraps everything, keep looking at the same message
+        if old_width != history_size.0 && self.ui.scroll.get() > 0 {
+            self.ui.scroll.set(reflow_scroll(&self.ui.history, old_width, history_size.0, self.ui.scroll.get()));
+        }
+        let mut history = if stamps_width > 0 && !self.ui.history.is_empty() {
+            visible_rows(&self.ui.history, history_size.0, history_size.1, self.ui.scroll.get()).into_iter().map(|(index, row)| {
+                let line = index.map(|index| self.ui.trimmed + index);
+                match line.and_then(|line| self.ui.stamps.iter().find(|(stamped, _)| *stamped == line)) {
+                    Some((_, stamp)) => stamp_row(row, history_size.0, stamp),
+                    None => row
+                }
+            }).collect()
+        } else {
+            history_view(&self.ui.history, history_size.0, history_size.1, self.ui.scroll.get())
+        };
+        if let Some((username, text)) = &self.pinned {
+            history.insert(0, pin_row(username, text, widthleft.saturating_sub(2) as usize));
+        }
+        let mut history_block = self.block(Line::from(history_title(&self.ui.topic, self.ui.roomusers.len())).centered());
+        if self.ui.dnd {
+            history_block = history_block.title(Line::from(" do not disturb ").magenta().right_aligned());
+        }
+        // a few stray packets happen, a steady stream of them means we're talking past each other
+        if self.ui.unreadable >= UNREADABLE_WARNING {
+            history_block = history_block.title_bottom(Line::from(format!(" {} unreadable packets, wrong room key or outdated Blossom? ", self.ui.unreadable)).red().centered());
+        }
+        let typing = self.ui.typing.names(Instant::now());
+        if !typing.is_empty() {
+            // a new dot every 400ms
+            let frame = chrono::Local::now().timestamp_millis() as usize / 400;
+            history_block = history_block.title_bottom(Line::from(format!(" {} ", typing::indicator(&typing, frame))).dim().italic().left_aligned());
+        }
+        if self.reconnects > 0 && self.joining.is_some() && !self.reconnect_hidden {
+            let frame = chrono::Local::now().timestamp_millis() as usize / 100;
+            let banner = format!(" {} Reconnecting to {}, attempt {}. Esc hides this ", spinner(frame), self.connectaddr, self.reconnects);
+            history_block = history_block.title(Line::from(banner).black().on_yellow().bold().centered());
         }
-        if history.len() > (heightleft - 6) as usize {
-            history.drain(0..(history.len() - (heightleft - 6) as usize));
+        if self.ui.unread > 0 {
+            history_block = history_block.title_bottom(Line::from(format!(" {} new, End to jump down ", self.ui.unread)).yellow().right_aligned());
         }
         Paragraph::new(history)
-            .block(block.to_owned().title(Line::from(" Blossom ").centered()))
-            .style(style.to_owned())
-            .render(Rect { x: area.width - widthleft, y: area.height - heightleft, width: widthleft, height: heightleft - 4 }, buf);
+            .block(history_block)
+            .style(style)
+            .render(Rect { x: area.width - widthleft, y: area.height - heightleft, width: widthleft, height: heightleft - inputheight - statusheight }, buf);
+
+        if statusheight > 0 {
+            let info = status::Info {
+                connected: self.localaddr.is_some() && self.joining.is_none(),
+                users: self.ui.roomusers.len(),
+                server: self.connectaddr.clone(),
+                port: self.localaddr.map(|addr| addr.port()),
+                rtt: self.stats.rtt,
+                unread: self.ui.unread,
+                time: chrono::Local::now().format(&self.time_format).to_string(),
+            };
+            Paragraph::new(status::line(&self.status, &info))
+                .block(self.block(""))
+                .style(style)
+                .render(Rect { x: area.width - widthleft, y: area.height - inputheight - statusheight, width: widthleft, height: statusheight }, buf);
+        }
 
-        let input = Paragraph::new(self.ui.input.clone());
-        input.block(block.title(" Message "))
+        self.ui.cursor.set(cursor.map(|(column, row)| Position { x: area.width - widthleft + 1 + column as u16, y: area.height - inputheight + 1 + row as u16 }));
+        let replying = self.selected.and_then(|line| self.said.iter().find(|said| said.line == line));
+        let mut input_block = match replying {
+            Some(said) => self.block(format!(" Replying to {}, Esc to cancel ", said.username)),
+            None => self.block(" Message ")
+        };
+        if self.input_error.is_some_and(|since| since.elapsed() < ERROR_LENGTH) {
+            input_block = input_block.border_style(Style::new().red());
+        }
+        if self.quit_pending() {
+            let prompt = match self.outbox.len() {
+                0 => " Press Ctrl+C again to quit ".to_string(),
+                1 => " 1 message hasn't reached the room yet, press Ctrl+C again to quit anyway ".to_string(),
+                pending => format!(" {} messages haven't reached the room yet, press Ctrl+C again to quit anyway ", pending)
+            };
+            input_block = input_block.title_bottom(Line::from(prompt).yellow().centered());
+        }
+        Paragraph::new(input)
+            .block(input_block)
             .style(style)
-            .render(Rect { x: area.width - widthleft, y: area.height - 4, width: widthleft, height: 4 }, buf);
+            .render(Rect { x: area.width - widthleft, y: area.height - inputheight, width: widthleft, height: inputheight }, buf);
+
+        // the preview covers the middle of the screen while it lasts, themed along with everything else
+        let theme = match self.preview {
+            Some((theme, since)) if since.elapsed() < PREVIEW_LENGTH => {
+                let sample = theme::sample();
+                let width = (sample.iter().map(Line::width).max().unwrap_or(0) as u16 + 4).min(area.width);
+                let height = (sample.len() as u16 + 2).min(area.height);
+                let popup = Rect { x: (area.width - width) / 2, y: (area.height - height) / 2, width, height };
+                Clear.render(popup, buf);
+                Paragraph::new(sample)
+                    .block(self.block(format!(" Preview: {} ", theme.name())).padding(Padding::horizontal(1)))
+                    .render(popup, buf);
+                theme
+            }
+            _ => self.theme
+        };
+        if let Some(background) = self.background {
+            theme::fill_background(area, buf, background);
+        }
+        theme.apply(area, buf);
+
+        // colors come from all over the place, so what the terminal can't show is
+        // taken out after drawing instead of at every spot that sets one
+        self.colors.apply(area, buf);
     }
 }
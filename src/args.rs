@@ -0,0 +1,110 @@
+use std::{fs, io::{Error, ErrorKind}, path::PathBuf};
+
+use crate::{config::Config, protocol::{self, MAX_USERNAME}, theme::{Colors, Theme}, transport};
+
+pub const HELP: &str = r#"
+Usage: program_name [OPTIONS]
+
+Options:
+  -u, --username <USERNAME>    Specify the username to display in the chat, 64 bytes at most.
+  -r, --roomkey <ROOMKEY>      Specify the room key to join a chat room.
+      --roomkey-file <PATH>    Read the room key from a file, so it stays out of the shell
+                               history and process list.
+      --roomkey-stdin          Read the room key from the first line of standard input.
+      --servers <KEY,KEY,...>  Room keys to try in order, moving on to the next one when
+                               a room doesn't answer. Tried after --roomkey.
+  -p, --port <PORT>            Specify the port number to use for the connection. Hosts use
+                               9191 by default, guests get a free one from the system.
+  -t, --transport <udp|tcp>    Talk to the host over UDP (default) or TCP.
+      --topic <TOPIC>          Set the topic when creating a room.
+      --bind <ADDRESS>         Host: listen on this address instead of only the yggdrasil
+                               one, like 0.0.0.0 for the LAN. Guest: send from it.
+      --log-file <PATH>        Write logs to this file, filtered by RUST_LOG.
+      --offline                Try Blossom out without a room or network, messages only
+                               come back from a pretend user.
+      --quiet                  Leave users joining and leaving out of the history.
+      --show-users             Start with the users panel shown, whatever F1 saved.
+      --no-color               Draw without colors. Setting NO_COLOR does the same.
+      --theme <default|high-contrast>
+                               Pick the colors, high-contrast is easier to read.
+  -V, --version                Print the version and the protocol version it talks.
+  -h, --help                   Display this help message.
+"#;
+
+/// What the command line asked for, besides the settings it overrides in
+/// the config.
+#[derive(Debug)]
+pub struct Args {
+    pub username: String,
+    pub roomkey: String,
+    // main reads it, after the flags are all known to be fine
+    pub roomkey_stdin: bool,
+    pub servers: Vec<String>,
+    // None picks the default, which differs for hosts and guests
+    pub port: Option<String>,
+    pub topic: String,
+    pub log_file: Option<PathBuf>,
+    pub offline: bool,
+    pub version: bool,
+    // anything unknown shows the help too
+    pub help: bool,
+}
+
+/// Reads the arguments, without the program name in front. A flag missing
+/// its value or given one it can't use is an error naming the flag.
+pub fn parse(args: impl IntoIterator<Item = String>, config: &mut Config) -> Result<Args, Error> {
+    let mut parsed = Args { username: String::new(), roomkey: String::new(), roomkey_stdin: false, servers: Vec::new(), port: None, topic: String::new(), log_file: None, offline: false, version: false, help: false };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "--username" | "-u" => {
+                parsed.username = value()?;
+                if parsed.username.contains('|') || parsed.username.len() > MAX_USERNAME {
+                    return Err(invalid(&arg, &format!("a name of at most {} bytes, without \"|\"", MAX_USERNAME)));
+                }
+            }
+            "--roomkey" | "-r" => parsed.roomkey = value()?,
+            "--roomkey-file" => {
+                let path = value()?;
+                let contents = fs::read_to_string(&path).map_err(|e| Error::new(e.kind(), format!("can't read {} {}: {}", arg, path, e)))?;
+                parsed.roomkey = roomkey(&contents).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} {} is empty", arg, path)))?;
+            }
+            "--roomkey-stdin" => parsed.roomkey_stdin = true,
+            "--servers" => parsed.servers = value()?.split(',').map(str::trim).filter(|key| !key.is_empty()).map(String::from).collect(),
+            "--port" | "-p" => parsed.port = Some(value()?),
+            "--transport" | "-t" => config.transport = transport::Kind::parse(&value()?).ok_or_else(|| invalid(&arg, "udp or tcp"))?,
+            "--topic" => parsed.topic = value()?,
+            "--quiet" => config.quiet = true,
+            "--show-users" => config.show_users = true,
+            "--no-color" => config.color = Some(Colors::Mono),
+            "--theme" => config.theme = Theme::parse(&value()?).ok_or_else(|| invalid(&arg, "default or high-contrast"))?,
+            "--bind" => config.bind = Some(value()?.parse().map_err(|_| invalid(&arg, "an IP address"))?),
+            "--log-file" => parsed.log_file = Some(PathBuf::from(value()?)),
+            "--offline" => parsed.offline = true,
+            "-V" | "--version" => parsed.version = true,
+            _ => {
+                parsed.help = true;
+                break;
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// What --version prints. Blossoms talking different protocol versions
+/// can't understand each other, whatever their own versions are.
+pub fn version() -> String {
+    format!("blossom {} (protocol {})", env!("CARGO_PKG_VERSION"), protocol::VERSION)
+}
+
+/// The key out of a file or standard input: the first line, without the
+/// newline or any spaces around it.
+pub fn roomkey(contents: &str) -> Option<String> {
+    let key = contents.lines().next()?.trim();
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+fn invalid(flag: &str, expected: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("{} takes {}", flag, expected))
+}
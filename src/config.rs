@@ -0,0 +1,494 @@
+use std::{env, fs, io::{Error, ErrorKind}, net::IpAddr, path::PathBuf, time::Duration};
+use chrono::format::{Item, StrftimeItems};
+use ratatui::{style::Color, symbols::border};
+
+use crate::{groups::{self, Groups}, notify::{self, Keywords, Level}, prefix::{self, Piece}, protocol::{HEARTBEAT, MAX_USERNAME}, status::{self, Segment}, theme::{Colors, Theme}, transport, typing};
+
+/// How far PageUp and PageDown scroll the history.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Page {
+    Full,
+    Half,
+    Rows(usize),
+}
+
+impl Page {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "full" => Some(Page::Full),
+            "half" => Some(Page::Half),
+            _ => value.parse().ok().filter(|rows| *rows > 0).map(Page::Rows)
+        }
+    }
+
+    /// How many rows one page is, with the history `height` rows tall. At
+    /// least one, so scrolling never gets stuck.
+    pub fn rows(&self, height: usize) -> usize {
+        match self {
+            Page::Full => height,
+            Page::Half => height / 2,
+            Page::Rows(rows) => *rows,
+        }.max(1)
+    }
+}
+
+/// What goes between messages from different senders, or ones far apart.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Separator {
+    Off,
+    Line,
+    Blank,
+}
+
+impl Separator {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Separator::Off),
+            "line" => Some(Separator::Line),
+            "blank" => Some(Separator::Blank),
+            _ => None
+        }
+    }
+}
+
+/// When new messages bring the history back down to them.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Follow {
+    // every new message, even when scrolled up
+    Always,
+    // only if the newest message was in view already
+    Bottom,
+    // only End does
+    Never,
+}
+
+impl Follow {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "always" => Some(Follow::Always),
+            "bottom" => Some(Follow::Bottom),
+            "never" => Some(Follow::Never),
+            _ => None
+        }
+    }
+
+    /// Whether the view stays where it is when a line comes in while it's
+    /// `scroll` rows up from the newest one.
+    pub fn holds(self, scroll: usize) -> bool {
+        match self {
+            Follow::Always => false,
+            Follow::Bottom => scroll > 0,
+            Follow::Never => true
+        }
+    }
+}
+
+/// Two sets of layout settings, switched between with F5. Compact fits
+/// the most messages on a small screen, comfortable sets them apart.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Density {
+    Compact,
+    Comfortable,
+}
+
+impl Density {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "compact" => Some(Density::Compact),
+            "comfortable" => Some(Density::Comfortable),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Density::Compact => "compact",
+            Density::Comfortable => "comfortable",
+        }
+    }
+
+    /// Overrides the settings the preset is made of, whatever the config
+    /// file says about them. Nothing keeps the config's own values, they're
+    /// only back once `density` is unset and Blossom starts again.
+    pub fn apply(&self, config: &mut Config) {
+        let compact = *self == Density::Compact;
+        config.separators = if compact { Separator::Off } else { Separator::Line };
+        config.timestamps = if compact { Timestamps::Off } else { Timestamps::Left };
+        config.merge_senders = compact;
+        config.compact_newlines = compact;
+        config.gutter = !compact;
+    }
+}
+
+/// Where the time each message came in goes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timestamps {
+    Off,
+    Left,
+    Right,
+}
+
+impl Timestamps {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Timestamps::Off),
+            "left" => Some(Timestamps::Left),
+            "right" => Some(Timestamps::Right),
+            _ => None
+        }
+    }
+}
+
+/// Settings read from the config file at `$XDG_CONFIG_HOME/blossom/config`
+/// (or `~/.config/blossom/config`).
+///
+/// The file is a list of `key = value` lines. Empty lines and lines starting
+/// with `#` are ignored. A missing file simply means default settings.
+///
+/// ```text
+/// # used when --username isn't given, a random one if this is empty too
+/// username = alice
+/// notify = deploy, coffee
+/// notify_mode = word
+/// notify_bell = true
+/// # which messages ring the bell: all, mentions (keywords) or muted
+/// notify_level = mentions
+/// # the same for single rooms, by room key. /notify all|mentions|muted sets these
+/// notify_rooms = WzIwMDo6MV06OTU5NQ==:muted
+/// # who /msg @name sends to, written by /group
+/// groups = team: alice bob, ops: carol
+/// # words hidden behind asterisks in messages from others
+/// filter = heck, darn
+/// border = rounded
+/// # seconds without input before showing as away, 0 to never go away
+/// away_after = 300
+/// # when hosting, seconds before a user nothing is heard from is taken out of the room, 0 for never
+/// presence_timeout = 90
+/// transport = udp
+/// history_limit = 5000
+/// # how far PageUp and PageDown scroll: full, half or a number of rows
+/// page = 10
+/// # when new messages scroll the history down to them: always, bottom (if it was there) or never
+/// auto_scroll = bottom
+/// # keep sent messages for Ctrl+P across runs, see sent::path
+/// save_history = false
+/// # no lines in the history for users joining and leaving, like --quiet
+/// quiet = false
+/// # ask for a second Ctrl+C before quitting
+/// confirm_quit = true
+/// # whether the users panel and the room key start out shown, F1 and F2 save these
+/// show_users = true
+/// show_key = true
+/// # the first letter of the sender before each message
+/// gutter = false
+/// # leave the name out of messages that quickly follow one from the same sender
+/// merge_senders = false
+/// # a thin line or an empty one between messages from different senders, or off
+/// separators = off
+/// # seconds after which messages from the same sender get one too, 0 for never
+/// separator_gap = 300
+/// # compact or comfortable to set separators, timestamps, merge_senders, newlines and gutter
+/// # all at once, F5 switches between the two. Either one replaces what those five say here,
+/// # wherever they are in the file. Unset to use them as they are
+/// #density = compact
+/// # the time each message came in: off, left in front of it or right at the end of its row
+/// timestamps = off
+/// # how times are written, see https://docs.rs/chrono/latest/chrono/format/strftime
+/// time_format = %H:%M
+/// # what goes in front of messages: {time}, {user} and text, ending in {msg}
+/// message_format = [{user}] {msg}
+/// # longer names are cut short in front of messages, 0 to show them whole
+/// name_width = 16
+/// # how many rows the input box grows to before older ones scroll away
+/// input_rows = 5
+/// # lines to show each line of a message on its own, compact to keep a message on one with ⏎ between its lines
+/// newlines = lines
+/// # auto picks by $TERM and $COLORTERM. false (or mono) draws without colors like NO_COLOR does,
+/// # 16 or 256 keep to that many, true (or truecolor) sends every color as it is
+/// color = auto
+/// # default or high-contrast
+/// theme = default
+/// # a color name like black or #1e1e2e to paint behind everything, transparent for the terminal's own
+/// background = transparent
+/// # what the status line above the input shows, in this order. Empty for none
+/// status = connection, server, port, users, rtt, unread, time
+/// # enter, or ctrl_enter to send with Ctrl+Enter and make Enter a new line
+/// send_on = enter
+/// # tell others when you've seen their messages
+/// read_receipts = false
+/// # deflate long messages before sending, everyone in the room needs a Blossom that reads them
+/// compress = false
+/// # seconds between telling the room we're still typing, 1 to 4
+/// typing_refresh = 3
+/// # milliseconds at least between typing or away updates of one kind
+/// control_interval = 500
+/// ```
+pub struct Config {
+    pub username: String,
+    pub keywords: Keywords,
+    pub notify_level: Level,
+    pub notify_rooms: Vec<(String, Level)>,
+    pub groups: Groups,
+    // matched like keywords, whole words only
+    pub filter: Keywords,
+    pub border: border::Set,
+    pub away_after: Option<Duration>,
+    pub presence_timeout: Option<Duration>,
+    pub transport: transport::Kind,
+    pub history_limit: usize,
+    pub page: Page,
+    pub auto_scroll: Follow,
+    pub confirm_quit: bool,
+    pub show_users: bool,
+    pub show_key: bool,
+    pub save_history: bool,
+    pub quiet: bool,
+    pub gutter: bool,
+    pub merge_senders: bool,
+    pub separators: Separator,
+    pub separator_gap: Option<Duration>,
+    pub name_width: usize,
+    pub timestamps: Timestamps,
+    pub density: Option<Density>,
+    pub time_format: String,
+    pub message_format: Vec<Piece>,
+    // a message typed over several lines stays on one history line
+    pub compact_newlines: bool,
+    pub input_rows: usize,
+    // None guesses from the terminal
+    pub color: Option<Colors>,
+    pub theme: Theme,
+    pub background: Option<Color>,
+    pub status: Vec<Segment>,
+    pub typing_refresh: Duration,
+    // Ctrl+Enter sends and Enter starts a new line, instead of the other way around
+    pub send_on_ctrl_enter: bool,
+    pub read_receipts: bool,
+    pub compress: bool,
+    pub control_interval: Duration,
+    // only set from the command line
+    pub bind: Option<IpAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { username: String::new(), keywords: Keywords::default(), notify_level: Level::Mentions, notify_rooms: Vec::new(), groups: Vec::new(), filter: Keywords::default(), border: border::PLAIN, away_after: Some(Duration::from_secs(300)), presence_timeout: Some(Duration::from_secs(90)), transport: transport::Kind::Udp, history_limit: 5000, page: Page::Rows(10), auto_scroll: Follow::Bottom, confirm_quit: true, show_users: true, show_key: true, save_history: false, quiet: false, gutter: false, merge_senders: false, separators: Separator::Off, separator_gap: Some(Duration::from_secs(300)), name_width: 16, timestamps: Timestamps::Off, density: None, time_format: "%H:%M".to_string(), message_format: prefix::parse(prefix::DEFAULT).unwrap_or_default(), compact_newlines: false, input_rows: 5, color: None, theme: Theme::Default, background: None, status: Vec::new(), typing_refresh: typing::REFRESH, send_on_ctrl_enter: false, read_receipts: false, compress: false, control_interval: Duration::from_millis(500), bind: None }
+    }
+}
+
+impl Config {
+
+    pub fn path() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+            return Some(PathBuf::from(dir).join("blossom").join("config"));
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("blossom").join("config"))
+    }
+
+    pub fn load() -> Result<Self, Error> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default())
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content).map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Writes a new config file with the answers from the first run. The
+    /// file didn't exist before, everything else keeps its default.
+    pub fn create(username: &str, transport: transport::Kind) -> Result<PathBuf, Error> {
+        let path = Self::path().ok_or_else(|| Error::new(ErrorKind::NotFound, "Neither XDG_CONFIG_HOME nor HOME is set"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let transport = match transport {
+            transport::Kind::Udp => "udp",
+            transport::Kind::Tcp => "tcp",
+        };
+        fs::write(&path, format!("# see the README for every setting\nusername = {}\ntransport = {}\n", username, transport))?;
+        Ok(path)
+    }
+
+    /// Changes one setting in the config file, or adds it if it isn't there,
+    /// leaving everything else as it was written.
+    pub fn save(key: &str, value: &str) -> Result<(), Error> {
+        let path = Self::path().ok_or_else(|| Error::new(ErrorKind::NotFound, "Neither XDG_CONFIG_HOME nor HOME is set"))?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e)
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, set(&content, key, value))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut config = Self::default();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(invalid(i, "expected `key = value`"))
+            };
+            match key {
+                "username" => {
+                    if value.contains('|') {
+                        return Err(invalid(i, "username can't contain `|`"));
+                    }
+                    if value.len() > MAX_USERNAME {
+                        return Err(invalid(i, &format!("username can be {} bytes long at most", MAX_USERNAME)));
+                    }
+                    config.username = value.to_string();
+                }
+                "notify" => {
+                    for word in value.split(',') {
+                        config.keywords.add(word);
+                    }
+                }
+                "filter" => {
+                    for word in value.split(',') {
+                        config.filter.add(word);
+                    }
+                }
+                "notify_mode" => config.keywords.substring = match value {
+                    "word" => false,
+                    "substring" => true,
+                    _ => return Err(invalid(i, "notify_mode must be `word` or `substring`"))
+                },
+                "notify_level" => config.notify_level = Level::parse(value).ok_or_else(|| invalid(i, "notify_level must be `all`, `mentions` or `muted`"))?,
+                "groups" => config.groups = groups::parse(value).ok_or_else(|| invalid(i, "groups must list `name: user user`, separated by commas"))?,
+                "notify_rooms" => config.notify_rooms = notify::parse_rooms(value).ok_or_else(|| invalid(i, "notify_rooms must list `roomkey:level`, each level `all`, `mentions` or `muted`"))?,
+                "notify_bell" => config.keywords.bell = parse_bool(value).ok_or_else(|| invalid(i, "notify_bell must be `true` or `false`"))?,
+                "border" => config.border = match value {
+                    "plain" => border::PLAIN,
+                    "rounded" => border::ROUNDED,
+                    "double" => border::DOUBLE,
+                    "thick" => border::THICK,
+                    _ => return Err(invalid(i, "border must be `plain`, `rounded`, `double` or `thick`"))
+                },
+                "away_after" => config.away_after = match value.parse::<u64>() {
+                    Ok(0) => None,
+                    Ok(secs) => Some(Duration::from_secs(secs)),
+                    Err(_) => return Err(invalid(i, "away_after must be a number of seconds"))
+                },
+                // a few heartbeats can go missing before someone is taken for gone
+                "presence_timeout" => config.presence_timeout = match value.parse::<u64>().map(Duration::from_secs) {
+                    Ok(timeout) if timeout.is_zero() => None,
+                    Ok(timeout) if timeout >= 3 * HEARTBEAT => Some(timeout),
+                    _ => return Err(invalid(i, &format!("presence_timeout must be 0 or at least {} seconds", 3 * HEARTBEAT.as_secs())))
+                },
+                "transport" => config.transport = transport::Kind::parse(value).ok_or_else(|| invalid(i, "transport must be `udp` or `tcp`"))?,
+                "history_limit" => config.history_limit = match value.parse::<usize>() {
+                    Ok(limit) if limit > 0 => limit,
+                    _ => return Err(invalid(i, "history_limit must be a number above 0"))
+                },
+                "page" => config.page = Page::parse(value).ok_or_else(|| invalid(i, "page must be `full`, `half` or a number of rows above 0"))?,
+                "confirm_quit" => config.confirm_quit = parse_bool(value).ok_or_else(|| invalid(i, "confirm_quit must be `true` or `false`"))?,
+                "show_users" => config.show_users = parse_bool(value).ok_or_else(|| invalid(i, "show_users must be `true` or `false`"))?,
+                "show_key" => config.show_key = parse_bool(value).ok_or_else(|| invalid(i, "show_key must be `true` or `false`"))?,
+                "quiet" => config.quiet = parse_bool(value).ok_or_else(|| invalid(i, "quiet must be `true` or `false`"))?,
+                "save_history" => config.save_history = parse_bool(value).ok_or_else(|| invalid(i, "save_history must be `true` or `false`"))?,
+                "gutter" => config.gutter = parse_bool(value).ok_or_else(|| invalid(i, "gutter must be `true` or `false`"))?,
+                "merge_senders" => config.merge_senders = parse_bool(value).ok_or_else(|| invalid(i, "merge_senders must be `true` or `false`"))?,
+                "auto_scroll" => config.auto_scroll = Follow::parse(value).ok_or_else(|| invalid(i, "auto_scroll must be `always`, `bottom` or `never`"))?,
+                "separators" => config.separators = Separator::parse(value).ok_or_else(|| invalid(i, "separators must be `off`, `line` or `blank`"))?,
+                "separator_gap" => config.separator_gap = match value.parse::<u64>() {
+                    Ok(0) => None,
+                    Ok(secs) => Some(Duration::from_secs(secs)),
+                    Err(_) => return Err(invalid(i, "separator_gap must be a number of seconds"))
+                },
+                "name_width" => config.name_width = value.parse().map_err(|_| invalid(i, "name_width must be a number of cells"))?,
+                "density" => config.density = Some(Density::parse(value).ok_or_else(|| invalid(i, "density must be `compact` or `comfortable`"))?),
+                "timestamps" => config.timestamps = Timestamps::parse(value).ok_or_else(|| invalid(i, "timestamps must be `off`, `left` or `right`"))?,
+                "time_format" => config.time_format = time_format(value).ok_or_else(|| invalid(i, "time_format must be a strftime format like `%H:%M` or `%-I:%M %p`"))?,
+                "message_format" => config.message_format = prefix::parse(value).ok_or_else(|| invalid(i, "message_format must end in {msg}, with {time} and {user} at most once each before it, like `{time} <{user}> {msg}`"))?,
+                "input_rows" => config.input_rows = match value.parse::<usize>() {
+                    Ok(rows) if rows > 0 => rows,
+                    _ => return Err(invalid(i, "input_rows must be a number above 0"))
+                },
+                "newlines" => config.compact_newlines = match value {
+                    "lines" => false,
+                    "compact" => true,
+                    _ => return Err(invalid(i, "newlines must be `lines` or `compact`"))
+                },
+                "color" => config.color = match value {
+                    "auto" => None,
+                    "true" => Some(Colors::TrueColor),
+                    "false" => Some(Colors::Mono),
+                    _ => Some(Colors::parse(value).ok_or_else(|| invalid(i, "color must be `auto`, `true`, `false`, `mono`, `16`, `256` or `truecolor`"))?)
+                },
+                "theme" => config.theme = Theme::parse(value).ok_or_else(|| invalid(i, "theme must be `default` or `high-contrast`"))?,
+                "background" => config.background = match value {
+                    "transparent" => None,
+                    _ => Some(value.parse().map_err(|_| invalid(i, "background must be `transparent`, a color name or #rrggbb"))?)
+                },
+                "status" => config.status = status::parse(value).ok_or_else(|| invalid(i, "status can only list `connection`, `server`, `port`, `users`, `rtt`, `unread` and `time`"))?,
+                "read_receipts" => config.read_receipts = parse_bool(value).ok_or_else(|| invalid(i, "read_receipts must be `true` or `false`"))?,
+                "compress" => config.compress = parse_bool(value).ok_or_else(|| invalid(i, "compress must be `true` or `false`"))?,
+                "send_on" => config.send_on_ctrl_enter = match value {
+                    "enter" => false,
+                    "ctrl_enter" => true,
+                    _ => return Err(invalid(i, "send_on must be `enter` or `ctrl_enter`"))
+                },
+                "typing_refresh" => config.typing_refresh = match value.parse::<u64>().map(Duration::from_secs) {
+                    Ok(refresh) if !refresh.is_zero() && refresh <= typing::MAX_REFRESH => refresh,
+                    _ => return Err(invalid(i, "typing_refresh must be a number of seconds from 1 to 4"))
+                },
+                "control_interval" => config.control_interval = match value.parse::<u64>() {
+                    Ok(millis) => Duration::from_millis(millis),
+                    Err(_) => return Err(invalid(i, "control_interval must be a number of milliseconds"))
+                },
+                _ => return Err(invalid(i, &format!("unknown setting `{}`", key)))
+            }
+        }
+
+        // wherever it is in the file, the preset wins over the settings it's made of
+        if let Some(density) = config.density {
+            density.apply(&mut config);
+        }
+        Ok(config)
+    }
+}
+
+/// The config file `content` with `key` set to `value`. The last line
+/// setting the key is the one that counts, so that's the one replaced.
+pub fn set(content: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let setting = format!("{} = {}", key, value);
+    let found = lines.iter().rposition(|line| {
+        let line = line.trim();
+        !line.starts_with('#') && line.split_once('=').is_some_and(|(k, _)| k.trim() == key)
+    });
+    match found {
+        Some(index) => lines[index] = setting,
+        None => lines.push(setting)
+    }
+    lines.join("\n") + "\n"
+}
+
+/// A strftime format for times, if chrono understands all of it.
+pub fn time_format(value: &str) -> Option<String> {
+    let valid = !value.is_empty() && StrftimeItems::new(value).all(|item| item != Item::Error);
+    valid.then(|| value.to_string())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "on" | "yes" => Some(true),
+        "false" | "off" | "no" => Some(false),
+        _ => None
+    }
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line + 1, msg))
+}
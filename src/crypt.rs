@@ -1,6 +1,9 @@
 use aes_gcm::{aead::{Aead, AeadCore, Key, KeyInit, OsRng}, Aes256Gcm, Nonce};
 use rand::RngCore;
 
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+
 /// DEPRECATED
 #[allow(dead_code)]
 pub fn generate_aesgcm(roomkeybytes: [u8; 32]) -> Aes256Gcm {
@@ -74,25 +77,25 @@ pub fn encrypt(cipher: &Aes256Gcm, message: String) -> Vec<u8> {
     /// `encrypted_data` should be the nonce, and the remaining bytes should be the
     /// ciphertext.
     ///
-    /// If decryption fails, this function will cry and panic.
+    /// If decryption fails, for example because the message was encrypted
+    /// with another key, an error is returned.
     ///
     /// DEPRECATED
 #[allow(dead_code)]
 pub fn decrypt(cipher: &Aes256Gcm, encrypted_data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
-    // The first 12 bytes should be the nonce
-    if encrypted_data.len() < 12 {
+    // The first 12 bytes should be the nonce, and even an empty message has a tag after it
+    if encrypted_data.len() < NONCE_LENGTH + TAG_LENGTH {
         return Err("Encrypted data too short".into());
     }
     
     // Split the data into nonce and ciphertext
-    let (nonce_bytes, ciphertext) = encrypted_data.split_at(12);
+    let (nonce_bytes, ciphertext) = encrypted_data.split_at(NONCE_LENGTH);
     let nonce = Nonce::from_slice(nonce_bytes);
     
     // Decrypt the message
     let plaintext = cipher
         .decrypt(nonce, ciphertext)
-        .unwrap_or("Failed to decrypt this message".into());
-        //.map_err(|e| format!("Decryption error: {}", e))?;
+        .map_err(|_| "Failed to decrypt this message")?;
     
     // Convert the decrypted bytes to a string
     String::from_utf8(plaintext)
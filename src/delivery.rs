@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+/// How long a sent message waits for the server to relay it back before it
+/// counts as lost.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long an echo is still matched to a message that timed out, so one
+/// that was only slow gets its ✗ turned back into a ✓ instead of showing
+/// up a second time.
+const LATE: Duration = Duration::from_secs(60);
+
+/// Messages we sent that the server hasn't relayed back to us yet.
+///
+/// The server relays every chat packet to the whole room, the sender
+/// included, so getting our own message back means it made it to the
+/// server. Packets don't carry ids, so echoes are matched to the oldest
+/// pending message of the same kind with the same text.
+#[derive(Default)]
+pub struct Outbox {
+    pending: Vec<(usize, Kind, String, Instant)>,
+    // timed out, but an echo could still turn up
+    late: Vec<(usize, Kind, String, Instant)>,
+}
+
+/// What a message was sent as, so a reply or a /msg isn't taken for a chat
+/// message that happens to read the same.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Chat,
+    // to whom, and the quote of what we replied to
+    Reply(String, String),
+    // the recipients
+    Private(Vec<String>),
+}
+
+impl Outbox {
+
+    /// Remembers a message shown at history line `line`.
+    pub fn sent(&mut self, line: usize, kind: Kind, message: String, now: Instant) {
+        self.pending.push((line, kind, message, now));
+    }
+
+    /// Returns the history line of the message the echo belongs to and when
+    /// it was sent, or `None` if we weren't waiting for it. Messages that
+    /// timed out not long ago still count.
+    pub fn echoed(&mut self, kind: &Kind, message: &str) -> Option<(usize, Instant)> {
+        let matches = |(_, k, m, _): &(usize, Kind, String, Instant)| k == kind && m == message;
+        let queue = if self.pending.iter().any(matches) { &mut self.pending } else { &mut self.late };
+        let index = queue.iter().position(matches)?;
+        let (line, _, _, sent) = queue.remove(index);
+        Some((line, sent))
+    }
+
+    /// Moves the messages after history line `after` by `by` lines, for when
+    /// a message above them changed its number of lines.
+    pub fn shift(&mut self, after: usize, by: isize) {
+        for (line, _, _, _) in self.pending.iter_mut().chain(self.late.iter_mut()) {
+            if *line > after {
+                *line = line.saturating_add_signed(by);
+            }
+        }
+    }
+
+    /// How many messages are still waiting.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the history lines of messages that waited too long. They no
+    /// longer count as waiting, but a late echo is still matched to them for
+    /// a while.
+    pub fn expired(&mut self, now: Instant) -> Vec<usize> {
+        self.late.retain(|(_, _, _, sent)| now.duration_since(*sent) < LATE);
+        let (expired, pending): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(|(_, _, _, sent)| now.duration_since(*sent) >= TIMEOUT);
+        self.pending = pending;
+        let lines = expired.iter().map(|(line, _, _, _)| *line).collect();
+        self.late.extend(expired);
+        lines
+    }
+}
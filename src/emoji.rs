@@ -0,0 +1,73 @@
+/// Shortcodes that get swapped for their emoji when a message is sent.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("smile", "😄"),
+    ("grin", "😁"),
+    ("joy", "😂"),
+    ("wink", "😉"),
+    ("blush", "😊"),
+    ("heart_eyes", "😍"),
+    ("thinking", "🤔"),
+    ("neutral_face", "😐"),
+    ("sweat_smile", "😅"),
+    ("cry", "😢"),
+    ("sob", "😭"),
+    ("angry", "😠"),
+    ("scream", "😱"),
+    ("sunglasses", "😎"),
+    ("upside_down", "🙃"),
+    ("eyes", "👀"),
+    ("wave", "👋"),
+    ("clap", "👏"),
+    ("pray", "🙏"),
+    ("muscle", "💪"),
+    ("ok_hand", "👌"),
+    ("+1", "👍"),
+    ("thumbsup", "👍"),
+    ("-1", "👎"),
+    ("thumbsdown", "👎"),
+    ("heart", "❤️"),
+    ("broken_heart", "💔"),
+    ("fire", "🔥"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("tada", "🎉"),
+    ("rocket", "🚀"),
+    ("coffee", "☕"),
+    ("beer", "🍺"),
+    ("pizza", "🍕"),
+    ("cherry_blossom", "🌸"),
+    ("check", "✅"),
+    ("x", "❌"),
+    ("warning", "⚠️"),
+    ("100", "💯"),
+    ("skull", "💀"),
+    ("shrug", "🤷"),
+];
+
+/// Replaces every known `:shortcode:` with its emoji. Anything that isn't a
+/// known shortcode, like the colons in "12:30:45", is left as typed.
+pub fn expand(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let emoji = after.find(':').and_then(|end| {
+            SHORTCODES.iter().find(|(code, _)| *code == &after[..end]).map(|(code, emoji)| (code.len(), *emoji))
+        });
+        match emoji {
+            Some((len, emoji)) => {
+                expanded.push_str(emoji);
+                rest = &after[len + 1..];
+            }
+            None => {
+                // the closing colon may open the next shortcode
+                expanded.push(':');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
@@ -0,0 +1,36 @@
+/// Names for sets of users, to send to all of them at once with
+/// `/msg @name`. They are only known to whoever defined them.
+pub type Groups = Vec<(String, Vec<String>)>;
+
+/// Reads `name: user user, name: user`, as `groups` in the config. Names
+/// with spaces, commas or colons in them can't be in a group.
+pub fn parse(value: &str) -> Option<Groups> {
+    value.split(',').map(str::trim).filter(|group| !group.is_empty()).map(|group| {
+        let (name, members) = group.split_once(':')?;
+        let members: Vec<String> = members.split_whitespace().map(String::from).collect();
+        (!name.trim().is_empty() && !members.is_empty()).then(|| (name.trim().to_string(), members))
+    }).collect()
+}
+
+/// Writes the groups back the way `parse` reads them.
+pub fn format(groups: &Groups) -> String {
+    groups.iter().map(|(name, members)| format!("{}: {}", name, members.join(" "))).collect::<Vec<_>>().join(", ")
+}
+
+/// Turns `alice,@team` into the users it stands for, each once. A group
+/// that doesn't exist is returned as the error.
+pub fn expand(targets: &str, groups: &Groups) -> Result<Vec<String>, String> {
+    let mut users: Vec<String> = Vec::new();
+    for target in targets.split(',').filter(|target| !target.is_empty()) {
+        let members = match target.strip_prefix('@') {
+            Some(group) => groups.iter().find(|(name, _)| name == group).map(|(_, members)| members.clone()).ok_or_else(|| group.to_string())?,
+            None => vec![target.to_string()]
+        };
+        for member in members {
+            if !users.contains(&member) {
+                users.push(member);
+            }
+        }
+    }
+    Ok(users)
+}
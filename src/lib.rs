@@ -0,0 +1,38 @@
+//! The chat engine behind the `blossom` binary, for front-ends and tests
+//! that want to drive a room themselves.
+//!
+//! Everything on the wire is a [`Packet`]. A [`Transport`] carries packets
+//! to a room's server, which [`server::create`] starts for a host, and
+//! [`App`] is the terminal front-end built on top of them. Who is in a room
+//! is kept in a [`Roster`].
+pub mod args;
+pub mod crypt;
+pub mod delivery;
+pub mod server;
+pub mod stats;
+pub mod status;
+mod tests;
+pub mod yggdrasil;
+pub mod app;
+pub mod config;
+pub mod emoji;
+pub mod groups;
+pub mod log;
+pub mod notify;
+pub mod prefix;
+pub mod presence;
+pub mod protocol;
+pub mod roster;
+pub mod sent;
+pub mod theme;
+pub mod throttle;
+pub mod transport;
+pub mod typing;
+pub mod wizard;
+pub mod wrap;
+
+pub use app::App;
+pub use config::Config;
+pub use protocol::{LeaveReason, Packet};
+pub use roster::Roster;
+pub use transport::Transport;
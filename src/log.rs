@@ -0,0 +1,18 @@
+use std::{fs::OpenOptions, io::Result, path::Path, sync::Mutex};
+use tracing_subscriber::EnvFilter;
+
+/// Writes logs to the file at `path`, appending to what is already there.
+///
+/// The terminal belongs to the UI while Blossom runs, so logs never go to
+/// stdout or stderr. Without a log file nothing is logged at all. `RUST_LOG`
+/// picks what gets logged, `info` if it isn't set.
+pub fn init(path: &Path) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .with_env_filter(filter)
+        .init();
+    Ok(())
+}
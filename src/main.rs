@@ -1,48 +1,113 @@
-use std::io;
-mod crypt;
-mod server;
-mod tests;
-mod yggdrasil;
-mod app;
-use app::App;
+use std::io::{self, BufRead, IsTerminal};
+use blossom::{app::App, args, config, crypt, log, theme, wizard};
 
 //building a chat app here
 fn main() -> io::Result<()> {
-    let mut username = String::new();
-    let mut roomkey = String::new();
-    let mut port = "9191".to_string();
-    let help = r#"
-Usage: program_name [OPTIONS]
+    // nothing to go on yet, so ask instead of starting with defaults
+    let first_run = std::env::args().len() == 1 && config::Config::path().is_some_and(|path| !path.exists());
+    // flags override the config file
+    let mut config = config::Config::load()?;
+    // https://no-color.org: any value but an empty one turns colors off
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        config.color = Some(theme::Colors::Mono);
+    }
 
-Options:
-  -u, --username <USERNAME>    Specify the username to display in the chat.
-  -r, --roomkey <ROOMKEY>      Specify the room key to join a chat room.
-  -p, --port <PORT>            Specify the port number to use for the connection.
-  -h, --help                   Display this help message.
-"#;
+    let args::Args { mut username, mut roomkey, roomkey_stdin, mut servers, port, topic, log_file, offline, version, help } = match args::parse(std::env::args().skip(1), &mut config) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}, see --help", e);
+            std::process::exit(1);
+        }
+    };
+    if help {
+        println!("{}", args::HELP);
+        return Ok(());
+    }
+    if version {
+        println!("{}", args::version());
+        return Ok(());
+    }
+    if roomkey_stdin {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        roomkey = match args::roomkey(&line) {
+            Some(key) => key,
+            None => {
+                eprintln!("no room key on standard input, see --help");
+                std::process::exit(1);
+            }
+        };
+    }
+    // before the UI takes over the terminal, so problems opening the file can still be printed
+    if let Some(path) = log_file {
+        log::init(&path)?;
+    }
+    // without a terminal to draw on there is nothing to show, say so plainly
+    // instead of drawing into a pipe. Keys are read from the terminal itself when
+    // standard input was only there to hand over the room key.
+    if (!io::stdin().is_terminal() && !roomkey_stdin) || !io::stdout().is_terminal() {
+        eprintln!("Blossom needs an interactive terminal, it can't run with its input or output redirected.");
+        std::process::exit(1);
+    }
+    let mut terminal = match ratatui::try_init() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("Failed to set up the terminal: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // try_init already restores the terminal before the default hook prints
+    // the panic. The message goes to the log as well, where it's easy to
+    // find again once the terminal is scrolled past it.
+    let restore_and_print = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_and_print(info);
+        tracing::error!("{}", info);
+    }));
 
-    let mut args = std::env::args().skip(1);
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "--username" | "-u" => username = args.next().expect(help),
-            "--roomkey" | "-r" => roomkey = args.next().expect(help),
-            "--port" | "-p" => port = args.next().expect(help),
-            "--help" | "-h" => return Ok(println!("{}", help)),
-            _ => return Ok(println!("{}", help))
+    if first_run {
+        let answers = match wizard::run(&mut terminal) {
+            Ok(Some(answers)) => answers,
+            Ok(None) => {
+                ratatui::restore();
+                return Ok(());
+            }
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
+        };
+        if let Err(e) = config::Config::create(&answers.username, answers.transport) {
+            ratatui::restore();
+            return Err(io::Error::new(e.kind(), format!("Failed to write the config file: {}", e)));
         }
+        username = answers.username;
+        roomkey = answers.roomkey;
+        config.username = username.clone();
+        config.transport = answers.transport;
     }
-    let mut terminal = ratatui::init();
 
+    if username.is_empty() {
+        username = config.username.clone();
+    }
     if username.is_empty() {
         username = crypt::generate_rnd_str(10);
     }
 
-    let app_result = if roomkey.is_empty() {
+    // errors are returned only after the terminal is restored, so they end up readable
+    if !roomkey.is_empty() {
+        servers.insert(0, roomkey);
+    }
+    let app_result = if offline {
+        App::offline(username, config).run(&mut terminal)
+    }
+    else if servers.is_empty() {
         //BASE64_STANDARD.encode_string(crypt::generate_roomkey(), &mut roomkey);
-        App::create_room(username, port)?.run(&mut terminal)
+        App::create_room(username, port.unwrap_or_else(|| "9191".to_string()), topic, config).and_then(|mut app| app.run(&mut terminal))
     }
     else {
-        App::join_room(username, roomkey, port)?.run(&mut terminal)
+        // 0 lets the system pick a free port, so several guests fit on one machine
+        App::join_room(username, servers, port.unwrap_or_else(|| "0".to_string()), config).and_then(|mut app| app.run(&mut terminal))
     };
     
     ratatui::restore();
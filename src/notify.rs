@@ -0,0 +1,149 @@
+use std::ops::Range;
+use ratatui::{style::Stylize, text::Span};
+
+/// Which messages ring the bell.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    All,
+    // keywords only, when notify_bell is on
+    Mentions,
+    Muted,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "all" => Some(Level::All),
+            "mentions" => Some(Level::Mentions),
+            "muted" => Some(Level::Muted),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::All => "all",
+            Level::Mentions => "mentions",
+            Level::Muted => "muted",
+        }
+    }
+}
+
+/// Reads `key:level, key:level`, the level for each room by its room key.
+/// Room keys are base64, so they never have a colon in them.
+pub fn parse_rooms(value: &str) -> Option<Vec<(String, Level)>> {
+    value.split(',').map(str::trim).filter(|room| !room.is_empty()).map(|room| {
+        let (key, level) = room.rsplit_once(':')?;
+        Some((key.trim().to_string(), Level::parse(level.trim())?))
+    }).collect()
+}
+
+/// Writes the rooms back the way `parse_rooms` reads them.
+pub fn format_rooms(rooms: &[(String, Level)]) -> String {
+    rooms.iter().map(|(key, level)| format!("{}:{}", key, level.name())).collect::<Vec<_>>().join(", ")
+}
+
+/// Words that get highlighted (and optionally ring the bell) when they show
+/// up in a message, like IRC hilights.
+pub struct Keywords {
+    pub words: Vec<String>,
+    pub substring: bool,
+    pub bell: bool,
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        Self { words: Vec::new(), substring: false, bell: true }
+    }
+}
+
+impl Keywords {
+
+    /// Adds a keyword. Returns false if it was already in the list.
+    pub fn add(&mut self, word: &str) -> bool {
+        let word = word.trim().to_ascii_lowercase();
+        if word.is_empty() || self.words.contains(&word) {
+            return false;
+        }
+        self.words.push(word);
+        true
+    }
+
+    /// Removes a keyword. Returns false if it wasn't in the list.
+    pub fn remove(&mut self, word: &str) -> bool {
+        let word = word.trim().to_ascii_lowercase();
+        let len = self.words.len();
+        self.words.retain(|w| *w != word);
+        self.words.len() != len
+    }
+
+    /// Finds every keyword hit in the message and returns their byte ranges,
+    /// sorted and merged so they can be turned into spans directly.
+    ///
+    /// Matching is case-insensitive. Unless substring mode is on, a hit only
+    /// counts if it's not glued to other letters, so "cat" won't fire on "category".
+    pub fn find(&self, message: &str) -> Vec<Range<usize>> {
+        // ascii lowercase keeps byte offsets the same as the original message
+        let lower = message.to_ascii_lowercase();
+        let mut hits: Vec<Range<usize>> = Vec::new();
+
+        for word in self.words.iter() {
+            for (start, _) in lower.match_indices(word.as_str()) {
+                let end = start + word.len();
+                let whole_word = is_boundary(&lower[..start].chars().next_back()) && is_boundary(&lower[end..].chars().next());
+                if !self.substring && !whole_word {
+                    continue;
+                }
+                hits.push(start..end);
+            }
+        }
+
+        hits.sort_by_key(|hit| hit.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for hit in hits {
+            match merged.last_mut() {
+                Some(last) if hit.start <= last.end => last.end = last.end.max(hit.end),
+                _ => merged.push(hit),
+            }
+        }
+        merged
+    }
+}
+
+fn is_boundary(c: &Option<char>) -> bool {
+    match c {
+        Some(c) => !c.is_alphanumeric() && *c != '_',
+        None => true,
+    }
+}
+
+/// Replaces every character of the hits with `*`, for words that shouldn't
+/// be shown.
+pub fn mask(message: &str, hits: &[Range<usize>]) -> String {
+    let mut masked = String::with_capacity(message.len());
+    let mut last = 0;
+    for hit in hits {
+        masked.push_str(&message[last..hit.start]);
+        masked.extend(message[hit.start..hit.end].chars().map(|_| '*'));
+        last = hit.end;
+    }
+    masked.push_str(&message[last..]);
+    masked
+}
+
+/// Splits the message into spans, with keyword hits standing out from the rest.
+pub fn highlight(message: &str, hits: &[Range<usize>]) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for hit in hits {
+        if hit.start > last {
+            spans.push(message[last..hit.start].to_owned().gray());
+        }
+        spans.push(message[hit.start..hit.end].to_owned().yellow().bold());
+        last = hit.end;
+    }
+    if last < message.len() {
+        spans.push(message[last..].to_owned().gray());
+    }
+    spans
+}
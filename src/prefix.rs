@@ -0,0 +1,49 @@
+/// One piece of what's written in front of a message.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Piece {
+    Text(String),
+    Time,
+    User,
+}
+
+/// What messages have in front of them when `message_format` isn't set.
+pub const DEFAULT: &str = "[{user}] {msg}";
+
+/// Reads a template like `{time} <{user}> {msg}` into the pieces before the
+/// message. `{msg}` has to come last, since the message wraps under the
+/// pieces, and `{time}` and `{user}` can each be there once. Any other `{`
+/// makes the template invalid.
+pub fn parse(value: &str) -> Option<Vec<Piece>> {
+    let front = value.strip_suffix("{msg}")?;
+    let mut pieces = Vec::new();
+    let mut rest = front;
+    while !rest.is_empty() {
+        let piece = match rest.find('{') {
+            Some(0) => {
+                let end = rest.find('}')?;
+                let piece = match &rest[1..end] {
+                    "time" => Piece::Time,
+                    "user" => Piece::User,
+                    _ => return None
+                };
+                if pieces.contains(&piece) {
+                    return None;
+                }
+                rest = &rest[end + 1..];
+                piece
+            }
+            Some(start) => {
+                let text = Piece::Text(rest[..start].to_string());
+                rest = &rest[start..];
+                text
+            }
+            None => {
+                let text = Piece::Text(rest.to_string());
+                rest = "";
+                text
+            }
+        };
+        pieces.push(piece);
+    }
+    Some(pieces)
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use crate::protocol::LeaveReason;
+
+/// How long joins and leaves are held back to be merged into one line.
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Change {
+    Joined,
+    Left(LeaveReason),
+}
+
+/// Holds back joins and leaves for a moment, so a burst of them (like
+/// everyone rejoining after the host restarts) becomes a single summary line
+/// instead of flooding the history.
+#[derive(Default)]
+pub struct Presence {
+    pending: Vec<(Change, String)>,
+    since: Option<Instant>,
+}
+
+impl Presence {
+
+    pub fn push(&mut self, change: Change, username: String, now: Instant) {
+        self.since.get_or_insert(now);
+        self.pending.push((change, username));
+    }
+
+    /// Once the window has passed, returns the buffered changes grouped by
+    /// kind, in the order each kind was first seen.
+    pub fn flush(&mut self, now: Instant) -> Vec<(Change, Vec<String>)> {
+        match self.since {
+            Some(since) if now.duration_since(since) >= WINDOW => {}
+            _ => return Vec::new()
+        }
+        self.since = None;
+
+        let mut groups: Vec<(Change, Vec<String>)> = Vec::new();
+        for (change, username) in self.pending.drain(..) {
+            match groups.iter_mut().find(|(c, _)| *c == change) {
+                Some((_, usernames)) => usernames.push(username),
+                None => groups.push((change, vec![username])),
+            }
+        }
+        groups
+    }
+}
+
+/// "Alice", "Alice and Bob", "Alice, Bob and Carol", "Alice, Bob and 2 others"
+pub fn summarize(usernames: &[String]) -> String {
+    match usernames {
+        [] => String::new(),
+        [one] => one.clone(),
+        [first, second] => format!("{} and {}", first, second),
+        [first, second, third] => format!("{}, {} and {}", first, second, third),
+        [first, second, rest @ ..] => format!("{}, {} and {} others", first, second, rest.len()),
+    }
+}
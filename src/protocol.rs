@@ -0,0 +1,341 @@
+use std::{io::{Error, ErrorKind, Read, Result, Write}, net::{SocketAddr, UdpSocket}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+/// Largest payload a single UDP datagram can carry.
+pub const MAX_DATAGRAM: usize = 65507;
+
+/// Longest username the server lets in, in bytes. Every message carries
+/// its sender's name, so this keeps names from eating into the room left
+/// for the message.
+pub const MAX_USERNAME: usize = 64;
+
+/// Which revision of the packets below this is. It isn't sent, it's there
+/// for `--version`: it goes up whenever packets change in a way older
+/// clients can't read, like chat packets and replies starting to carry when
+/// they were sent, and two Blossoms with different ones can't talk to each other.
+pub const VERSION: u8 = 2;
+
+/// Allocates a receive buffer one byte larger than `MAX_DATAGRAM`, so a
+/// datagram that fills it completely can be told apart from one that fits.
+pub fn buffer() -> Vec<u8> {
+    vec![0; MAX_DATAGRAM + 1]
+}
+
+/// Receives a datagram into `buffer`.
+///
+/// The kernel silently cuts off datagrams that don't fit into the buffer, so
+/// a datagram that fills the whole buffer may have been truncated. It is
+/// reported as `InvalidData` instead of being handed over half-read.
+pub fn recv_from(socket: &UdpSocket, buffer: &mut [u8]) -> Result<(usize, SocketAddr)> {
+    let (size, addr) = socket.recv_from(buffer)?;
+    if size >= buffer.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("dropped a datagram larger than {} bytes", buffer.len().saturating_sub(1))));
+    }
+    Ok((size, addr))
+}
+
+/// First byte of a packet announcing a username to the room.
+pub const JOIN: u8 = 0x01;
+/// First byte of a chat packet, followed by when it was sent, in milliseconds
+/// since the Unix epoch by the sender's clock as eight big-endian bytes, and
+/// then `username|message`.
+pub const CHAT: u8 = 0x02;
+/// First byte of a packet telling the room someone is gone, followed by the
+/// reason byte and the username.
+pub const LEAVE: u8 = 0x03;
+/// First byte of a packet saying whether someone is at the keyboard, followed
+/// by 1 for away or 0 for active and the username.
+pub const STATUS: u8 = 0x04;
+/// First byte of a packet renaming a user, followed by the length of the old
+/// name as two big-endian bytes, the old name and the new name.
+pub const NICK: u8 = 0x05;
+/// First byte of a packet carrying the topic of the room, empty if it has none.
+pub const TOPIC: u8 = 0x06;
+/// First byte of a reply. When it was sent follows like in a chat packet,
+/// then the name of whoever is replied to and the start of their message,
+/// each after its length as two big-endian bytes, and then
+/// `username|message` like in a chat packet.
+pub const REPLY: u8 = 0x07;
+/// First byte of a packet saying whether someone is typing, followed by 1
+/// for typing or 0 for stopped and the username.
+pub const TYPING: u8 = 0x08;
+/// First byte of an edit. The message as it was follows after its length as
+/// two big-endian bytes, and then `username|message` with the new text.
+pub const EDIT: u8 = 0x09;
+/// First byte of a packet taking a message back, followed by
+/// `username|message` like in a chat packet.
+pub const UNSEND: u8 = 0x0A;
+/// First byte of a read receipt. Who wrote the message that was read and the
+/// message follow, each after its length as two big-endian bytes, and then
+/// the name of whoever read it.
+pub const READ: u8 = 0x0B;
+/// First byte of a heartbeat, which has nothing after it. Clients send one
+/// every `HEARTBEAT` so the server can tell a quiet user from a crashed one.
+pub const PING: u8 = 0x0D;
+/// First byte of a message for some of the room only. How many it's for
+/// follows as one byte, then each of their names after its length as two
+/// big-endian bytes, and then `username|message` like in a chat packet.
+pub const PRIVATE: u8 = 0x0E;
+/// First byte of a compressed packet, followed by any other packet squeezed
+/// with deflate. Only ever one layer deep.
+pub const DEFLATE: u8 = 0x0C;
+
+/// How often clients let the server know they're still there.
+pub const HEARTBEAT: Duration = Duration::from_secs(20);
+
+/// Packets shorter than this go out as they are, deflate's own overhead
+/// would eat most of what it saves on them.
+pub const COMPRESS_ABOVE: usize = 256;
+
+/// Now by our clock, the way chat packets carry it.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Why a user is no longer in the room.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LeaveReason {
+    Quit = 0,
+    Timeout = 1,
+    Kicked = 2,
+}
+
+impl LeaveReason {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(LeaveReason::Quit),
+            1 => Some(LeaveReason::Timeout),
+            2 => Some(LeaveReason::Kicked),
+            _ => None
+        }
+    }
+}
+
+/// Everything that travels between the clients and the server. Each packet
+/// starts with a tag byte saying what it is, so a short chat message can't
+/// be mistaken for a join no matter how long it is.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    Join(String),
+    Chat { username: String, message: String, sent: u64 },
+    Leave { username: String, reason: LeaveReason },
+    Status { username: String, away: bool },
+    Nick { old: String, new: String },
+    Topic(String),
+    Reply { username: String, message: String, to: String, quote: String, sent: u64 },
+    Typing { username: String, typing: bool },
+    Edit { username: String, old: String, message: String },
+    Unsend { username: String, message: String },
+    Read { username: String, to: String, message: String },
+    Ping,
+    Private { username: String, to: Vec<String>, message: String },
+}
+
+impl Packet {
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Packet::Join(username) => {
+                bytes.push(JOIN);
+                bytes.extend_from_slice(username.as_bytes());
+            }
+            Packet::Chat { username, message, sent } => {
+                bytes.push(CHAT);
+                bytes.extend_from_slice(&sent.to_be_bytes());
+                bytes.extend_from_slice(format!("{}|{}", username, message).as_bytes());
+            }
+            Packet::Leave { username, reason } => {
+                bytes.push(LEAVE);
+                bytes.push(*reason as u8);
+                bytes.extend_from_slice(username.as_bytes());
+            }
+            Packet::Status { username, away } => {
+                bytes.push(STATUS);
+                bytes.push(*away as u8);
+                bytes.extend_from_slice(username.as_bytes());
+            }
+            Packet::Nick { old, new } => {
+                bytes.push(NICK);
+                bytes.extend_from_slice(&(old.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(old.as_bytes());
+                bytes.extend_from_slice(new.as_bytes());
+            }
+            Packet::Topic(topic) => {
+                bytes.push(TOPIC);
+                bytes.extend_from_slice(topic.as_bytes());
+            }
+            Packet::Reply { username, message, to, quote, sent } => {
+                bytes.push(REPLY);
+                bytes.extend_from_slice(&sent.to_be_bytes());
+                for field in [to, quote] {
+                    bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+                    bytes.extend_from_slice(field.as_bytes());
+                }
+                bytes.extend_from_slice(format!("{}|{}", username, message).as_bytes());
+            }
+            Packet::Typing { username, typing } => {
+                bytes.push(TYPING);
+                bytes.push(*typing as u8);
+                bytes.extend_from_slice(username.as_bytes());
+            }
+            Packet::Edit { username, old, message } => {
+                bytes.push(EDIT);
+                bytes.extend_from_slice(&(old.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(old.as_bytes());
+                bytes.extend_from_slice(format!("{}|{}", username, message).as_bytes());
+            }
+            Packet::Unsend { username, message } => {
+                bytes.push(UNSEND);
+                bytes.extend_from_slice(format!("{}|{}", username, message).as_bytes());
+            }
+            Packet::Read { username, to, message } => {
+                bytes.push(READ);
+                for field in [to, message] {
+                    bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+                    bytes.extend_from_slice(field.as_bytes());
+                }
+                bytes.extend_from_slice(username.as_bytes());
+            }
+            Packet::Ping => bytes.push(PING),
+            Packet::Private { username, to, message } => {
+                bytes.push(PRIVATE);
+                // more than fit into the byte are cut off, no room has that many anyway
+                let to = &to[..to.len().min(u8::MAX as usize)];
+                bytes.push(to.len() as u8);
+                for name in to {
+                    bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    bytes.extend_from_slice(name.as_bytes());
+                }
+                bytes.extend_from_slice(format!("{}|{}", username, message).as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Like `encode`, but packets of `COMPRESS_ABOVE` bytes or more are
+    /// compressed when that makes them smaller. Clients that don't know
+    /// `DEFLATE` drop these, so it's for rooms where everyone has it.
+    pub fn encode_compressed(&self) -> Vec<u8> {
+        let bytes = self.encode();
+        if bytes.len() < COMPRESS_ABOVE {
+            return bytes;
+        }
+        let mut encoder = DeflateEncoder::new(vec![DEFLATE], Compression::default());
+        match encoder.write_all(&bytes).and_then(|_| encoder.finish()) {
+            Ok(compressed) if compressed.len() < bytes.len() => compressed,
+            _ => bytes
+        }
+    }
+
+    /// Returns `None` for anything that isn't a well-formed packet. Those are
+    /// dropped, since anyone can send bytes to an open UDP port.
+    pub fn decode(bytes: &[u8]) -> Option<Packet> {
+        match bytes.split_first()? {
+            (&DEFLATE, payload) => Packet::decode_plain(&inflate(payload)?),
+            _ => Packet::decode_plain(bytes)
+        }
+    }
+
+    fn decode_plain(bytes: &[u8]) -> Option<Packet> {
+        let (tag, payload) = bytes.split_first()?;
+        match *tag {
+            JOIN => parse_join(payload).map(Packet::Join),
+            CHAT => {
+                let (sent, rest) = payload.split_first_chunk::<8>()?;
+                let (username, message) = std::str::from_utf8(rest).ok()?.split_once('|')?;
+                Some(Packet::Chat { username: username.to_string(), message: message.to_string(), sent: u64::from_be_bytes(*sent) })
+            }
+            LEAVE => {
+                let (reason, username) = payload.split_first()?;
+                Some(Packet::Leave { username: parse_join(username)?, reason: LeaveReason::from_byte(*reason)? })
+            }
+            STATUS => {
+                let (away, username) = payload.split_first()?;
+                Some(Packet::Status { username: parse_join(username)?, away: parse_flag(*away)? })
+            }
+            TYPING => {
+                let (typing, username) = payload.split_first()?;
+                Some(Packet::Typing { username: parse_join(username)?, typing: parse_flag(*typing)? })
+            }
+            NICK => {
+                let len = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+                let names = &payload[2..];
+                if len > names.len() {
+                    return None;
+                }
+                let (old, new) = names.split_at(len);
+                Some(Packet::Nick { old: parse_join(old)?, new: parse_join(new)? })
+            }
+            TOPIC => std::str::from_utf8(payload).ok().map(|topic| Packet::Topic(topic.to_string())),
+            REPLY => {
+                let (sent, rest) = payload.split_first_chunk::<8>()?;
+                let (to, rest) = take(rest)?;
+                let (quote, rest) = take(rest)?;
+                let (username, message) = std::str::from_utf8(rest).ok()?.split_once('|')?;
+                Some(Packet::Reply { username: username.to_string(), message: message.to_string(), to: parse_join(to.as_bytes())?, quote: quote.to_string(), sent: u64::from_be_bytes(*sent) })
+            }
+            EDIT => {
+                let (old, rest) = take(payload)?;
+                let (username, message) = std::str::from_utf8(rest).ok()?.split_once('|')?;
+                Some(Packet::Edit { username: username.to_string(), old: old.to_string(), message: message.to_string() })
+            }
+            UNSEND => {
+                let (username, message) = std::str::from_utf8(payload).ok()?.split_once('|')?;
+                Some(Packet::Unsend { username: username.to_string(), message: message.to_string() })
+            }
+            READ => {
+                let (to, rest) = take(payload)?;
+                let (message, username) = take(rest)?;
+                Some(Packet::Read { username: parse_join(username)?, to: parse_join(to.as_bytes())?, message: message.to_string() })
+            }
+            PING => payload.is_empty().then_some(Packet::Ping),
+            PRIVATE => {
+                let (count, mut rest) = payload.split_first()?;
+                let mut to = Vec::new();
+                for _ in 0..*count {
+                    let (name, after) = take(rest)?;
+                    to.push(parse_join(name.as_bytes())?);
+                    rest = after;
+                }
+                let (username, message) = std::str::from_utf8(rest).ok()?.split_once('|')?;
+                Some(Packet::Private { username: username.to_string(), to, message: message.to_string() })
+            }
+            _ => None
+        }
+    }
+}
+
+/// Decompresses the payload of a `DEFLATE` packet. A few bytes can inflate
+/// to gigabytes, so anything that wouldn't have fit into a datagram
+/// uncompressed is dropped before it gets that far.
+fn inflate(payload: &[u8]) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    DeflateDecoder::new(payload).take(MAX_DATAGRAM as u64 + 1).read_to_end(&mut inflated).ok()?;
+    (inflated.len() <= MAX_DATAGRAM).then_some(inflated)
+}
+
+fn parse_flag(byte: u8) -> Option<bool> {
+    match byte {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None
+    }
+}
+
+/// Splits a string with its length in front as two big-endian bytes off the
+/// start of the payload.
+fn take(payload: &[u8]) -> Option<(&str, &[u8])> {
+    let len = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+    let field = payload.get(2..2 + len)?;
+    Some((std::str::from_utf8(field).ok()?, &payload[2 + len..]))
+}
+
+/// Reads the username out of a join packet. Returns `None` for packets that
+/// aren't valid UTF-8, which are dropped rather than trusted.
+pub fn parse_join(packet: &[u8]) -> Option<String> {
+    match std::str::from_utf8(packet) {
+        Ok(username) if !username.is_empty() => Some(username.to_string()),
+        _ => None
+    }
+}
@@ -0,0 +1,60 @@
+/// Someone in the room, as far as this client knows.
+pub struct User {
+    pub name: String,
+    pub away: bool,
+}
+
+/// Everyone in the room, in the order they joined. Each name is in it once,
+/// the server doesn't let two users share one.
+#[derive(Default)]
+pub struct Roster {
+    users: Vec<User>,
+}
+
+impl Roster {
+
+    /// Adds the user. Returns false if they were already in the room.
+    pub fn join(&mut self, name: String) -> bool {
+        if self.contains(&name) {
+            return false;
+        }
+        self.users.push(User { name, away: false });
+        true
+    }
+
+    /// Removes the user. Returns false if they weren't in the room.
+    pub fn leave(&mut self, name: &str) -> bool {
+        let len = self.users.len();
+        self.users.retain(|user| user.name != name);
+        self.users.len() != len
+    }
+
+    pub fn set_away(&mut self, name: &str, away: bool) {
+        if let Some(user) = self.users.iter_mut().find(|user| user.name == name) {
+            user.away = away;
+        }
+    }
+
+    /// Renames the user, keeping them where they were in the list.
+    pub fn rename(&mut self, old: &str, new: String) {
+        if let Some(user) = self.users.iter_mut().find(|user| user.name == old) {
+            user.name = new;
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.users.iter().any(|user| user.name == name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.users.iter()
+    }
+}
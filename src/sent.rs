@@ -0,0 +1,133 @@
+use std::{env, fs::{self, File, OpenOptions}, io::{ErrorKind, Result, Write}, path::{Path, PathBuf}};
+
+/// How many sent lines are kept to go back to.
+const LIMIT: usize = 100;
+
+/// What we typed in, newest last, to bring back with Ctrl+P and Ctrl+N like
+/// a shell's history.
+///
+/// With `save_history = true` the messages are kept in a file across runs.
+/// Commands and anything that looks like a secret are only kept in memory.
+#[derive(Default)]
+pub struct Sent {
+    entries: Vec<String>,
+    // the entry shown in the input while going through them
+    cursor: Option<usize>,
+}
+
+impl Sent {
+
+    /// Reads what earlier runs saved. A missing file is just an empty history.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self { entries: content.lines().map(unescape).collect(), cursor: None }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Writes the entries worth keeping, one per line.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let kept: String = self.entries.iter().filter(|entry| worth_saving(entry)).map(|entry| escape(entry) + "\n").collect();
+        create(path)?.write_all(kept.as_bytes())
+    }
+
+    /// Remembers a line that was just sent, and starts over from the newest.
+    pub fn push(&mut self, line: &str) {
+        self.cursor = None;
+        if line.is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > LIMIT {
+            self.entries.remove(0);
+        }
+    }
+
+    /// The entry before the one shown, or the newest one to begin with. Stays
+    /// on the oldest one.
+    pub fn older(&mut self) -> Option<&str> {
+        let index = match self.cursor {
+            Some(index) => index.saturating_sub(1),
+            None => self.entries.len().checked_sub(1)?
+        };
+        self.cursor = Some(index);
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// The entry after the one shown. Going past the newest gives `None`,
+    /// back to an empty input.
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.cursor? + 1;
+        self.cursor = (index < self.entries.len()).then_some(index);
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = None;
+    }
+}
+
+/// Where `save_history` keeps it: `$XDG_DATA_HOME/blossom/sent`, or
+/// `~/.local/share/blossom/sent`.
+pub fn path() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return Some(PathBuf::from(dir).join("blossom").join("sent"));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share").join("blossom").join("sent"))
+}
+
+/// Commands aren't saved, and neither is anything that could be a password,
+/// a token or a room key: a long word with no spaces and both letters and
+/// digits in it.
+pub fn worth_saving(line: &str) -> bool {
+    let secret = line.split_whitespace().any(|word| {
+        word.len() >= 16 && word.chars().any(|c| c.is_ascii_digit()) && word.chars().any(|c| c.is_alphabetic())
+    });
+    !line.starts_with('/') && !secret
+}
+
+// messages can be typed over several lines, the file has one per line
+/// Opens the file to save to, empty. What we typed is only for us to read,
+/// so the file is 0600 and a directory made for it 0700.
+#[cfg(unix)]
+fn create(path: &Path) -> Result<File> {
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    // a file saved before this was in place keeps its mode otherwise
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    File::create(path)
+}
+
+fn escape(line: &str) -> String {
+    line.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\')
+        }
+    }
+    unescaped
+}
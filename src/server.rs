@@ -1,9 +1,29 @@
-use std::{io::Result, net::{SocketAddr, UdpSocket}, process::Child, sync::mpsc::{Receiver, Sender}, thread};
+use std::{io::{Error, ErrorKind, Result}, net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket}, process::Child, sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError}, thread, time::{Duration, Instant}};
 use std::sync::mpsc;
 
-use crate::yggdrasil;
+use crate::{protocol::{self, LeaveReason, Packet, DEFLATE, MAX_USERNAME}, transport, yggdrasil};
 
-pub fn create() -> Result<(String, Child, Sender<()>)> {
+/// How often the server looks for users gone silent when nothing else
+/// happens.
+const SWEEP: Duration = Duration::from_secs(1);
+
+/// How many packets can wait for a TCP user before they're taken as not
+/// reading and hung up on.
+const BACKLOG: usize = 256;
+
+/// What the host can ask its server to do.
+pub enum Command {
+    Shutdown,
+    Kick(String),
+    Ban(String),
+    Topic(String),
+}
+
+/// Starts the room. The server listens on the yggdrasil address, or on `bind`
+/// if it's given. Returns the address that goes into the room key and the
+/// address the host's own client should connect to, which differ when the
+/// server listens somewhere else.
+pub fn create(topic: String, bind: Option<IpAddr>, timeout: Option<Duration>) -> Result<(String, String, Child, Sender<Command>)> {
 
     // start yggdrasil process and use it for exit later
     let ygg = yggdrasil::start()?;
@@ -18,59 +38,419 @@ pub fn create() -> Result<(String, Child, Sender<()>)> {
     connectaddr = connectaddr.replace("/64", ":9595");
     
 
-    let connectaddr_clone = connectaddr.clone();
+    // listening on every address includes the yggdrasil one, so the host can still use that
+    let listenaddr = match bind {
+        Some(ip) => SocketAddr::new(ip, 9595),
+        None => connectaddr.parse().map_err(|_| Error::new(ErrorKind::InvalidData, format!("\"{}\" is not an address to listen on", connectaddr)))?
+    };
+    let localaddr = match bind {
+        Some(ip) if !ip.is_unspecified() => listenaddr.to_string(),
+        _ => connectaddr.clone()
+    };
+
+    let (_, servertx) = listen(listenaddr, topic, timeout)?;
+
+    Ok((connectaddr, localaddr, ygg, servertx))
+}
+
+/// Starts a server on `addr`, over UDP and TCP on the same port, without
+/// yggdrasil. Port 0 picks a free one. Users nothing has been heard from in
+/// `timeout` are taken out of the room, `None` keeps them until they leave.
+/// Returns the address it listens on and the way to send it commands.
+pub fn listen(addr: SocketAddr, topic: String, timeout: Option<Duration>) -> Result<(SocketAddr, Sender<Command>)> {
+    let socket = UdpSocket::bind(addr)?;
+    let addr = socket.local_addr()?;
+    // same address and port for users whose network blocks UDP
+    let listener = TcpListener::bind(addr)?;
+
     let (servertx, serverrx) = mpsc::channel();
-    // start server
-    thread::spawn(move || { run(connectaddr_clone, serverrx); });
-    
-    Ok((connectaddr, ygg, servertx))
+    thread::spawn(move || run(socket, listener, serverrx, topic, timeout));
+    Ok((addr, servertx))
 }
 
 struct User {
     pub name: String,
-    pub addr: SocketAddr
+    pub peer: Peer,
+    pub away: bool,
+    // the last time anything came from them
+    pub heard: Instant,
 }
 
-fn run(connect_addr: String, serverrx: Receiver<()>) {
+/// Where a user is connected from.
+#[derive(PartialEq, Clone, Copy)]
+enum Peer {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
 
-    // wait for yggdrasil to start
-    //thread::sleep(time::Duration::from_millis(2000));
-    let socket = match UdpSocket::bind(connect_addr.clone()) {
-        Ok(s) => s,
-        Err(e) => panic!("Failed to bind to socket: {}\n{}", e, connect_addr),
-    };
+impl Peer {
+    fn ip(&self) -> IpAddr {
+        match self {
+            Peer::Udp(addr) | Peer::Tcp(addr) => addr.ip()
+        }
+    }
+}
+
+/// Everything the server reacts to, funneled into one channel so a single
+/// loop owns the room no matter how users are connected.
+enum Event {
+    Command(Command),
+    Packet(Peer, Vec<u8>),
+    Connected(SocketAddr, TcpStream),
+    Disconnected(SocketAddr),
+}
+
+/// Sends packets to users over whatever they are connected with.
+struct Peers {
+    socket: UdpSocket,
+    // each TCP user has a thread writing to them, the stream is kept to hang up
+    streams: Vec<(SocketAddr, TcpStream, SyncSender<Vec<u8>>)>,
+}
+
+impl Peers {
+    /// Starts writing to a new TCP user. Writes block while the user isn't
+    /// reading, so they happen away from the room's loop.
+    fn connect(&mut self, addr: SocketAddr, stream: TcpStream) {
+        let Ok(mut writer) = stream.try_clone() else {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        };
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(BACKLOG);
+        thread::spawn(move || {
+            while let Ok(packet) = rx.recv() {
+                if transport::write_frame(&mut writer, &packet).is_err() {
+                    return;
+                }
+            }
+        });
+        self.streams.push((addr, stream, tx));
+    }
+
+    fn send(&self, packet: &[u8], peer: Peer) {
+        match peer {
+            Peer::Udp(addr) => { let _ = self.socket.send_to(packet, addr); }
+            Peer::Tcp(addr) => {
+                if let Some((_, stream, tx)) = self.streams.iter().find(|(a, _, _)| *a == addr) {
+                    // a user this far behind isn't reading, hanging up ends their
+                    // reader too, which takes them out of the room
+                    if let Err(TrySendError::Full(_)) = tx.try_send(packet.to_vec()) {
+                        tracing::info!(%addr, "not reading, disconnected");
+                        let _ = stream.shutdown(Shutdown::Both);
+                    }
+                }
+            }
+        }
+    }
+
+    fn broadcast(&self, packet: &[u8], users: &[User]) {
+        for user in users.iter() {
+            self.send(packet, user.peer);
+        }
+    }
+}
+
+fn run(socket: UdpSocket, listener: TcpListener, serverrx: Receiver<Command>, mut topic: String, timeout: Option<Duration>) {
+
+    tracing::info!(addr = ?socket.local_addr(), "server listening");
+
+    let (eventtx, events) = mpsc::channel();
+
+    // commands from the host
+    let tx = eventtx.clone();
+    thread::spawn(move || {
+        for command in serverrx {
+            if tx.send(Event::Command(command)).is_err() {
+                return;
+            }
+        }
+        // the host is gone anyway
+        let _ = tx.send(Event::Command(Command::Shutdown));
+    });
+
+    // datagrams
+    let udp = socket.try_clone().unwrap();
+    let tx = eventtx.clone();
+    thread::spawn(move || {
+        let mut buffer = protocol::buffer();
+        loop {
+            match protocol::recv_from(&udp, &mut buffer) {
+                Ok((size, addr)) => {
+                    if tx.send(Event::Packet(Peer::Udp(addr), buffer[..size].to_vec())).is_err() {
+                        return;
+                    }
+                }
+                // oversized datagram, drop it instead of relaying a clipped one
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    tracing::warn!(error = %e, "dropped a datagram");
+                    continue;
+                }
+                Err(_) => return,
+            }
+        }
+    });
 
+    // streams
+    thread::spawn(move || accept(listener, eventtx));
+
+    let mut peers = Peers { socket, streams: Vec::new() };
     let mut users: Vec<User> = Vec::new();
+    // banned for the lifetime of the room
+    let mut banned: Vec<IpAddr> = Vec::new();
     loop {
-        let mut buffer = [0; 10240];
-        match socket.recv_from(&mut buffer) {
-            Ok((size, addr)) => {
-                if serverrx.try_recv().is_ok() { // exit signal check
-                    break;
-                }
-                
-                // check if the user is already in the room
-                if !users.iter().any(|user| user.addr == addr) {// user is not in the room yet
-                    // add him to the room
-                    users.push(User { name: String::from_utf8_lossy(&buffer[..size]).to_string(), addr });
-                    // send all usernames in the room to the new user
-                    for user in users.iter() {
-                        if user.addr == addr {// don't send the new user his own name
-                            continue;
-                        }
-                        // send message
-                        socket.send_to(user.name.as_bytes(), addr).unwrap();
+        if let Some(timeout) = timeout {
+            evict_silent(&peers, &mut users, timeout);
+        }
+        let (peer, packet) = match events.recv_timeout(SWEEP) {
+            Ok(Event::Packet(peer, packet)) => (peer, packet),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Ok(Event::Connected(addr, stream)) => {
+                peers.connect(addr, stream);
+                continue;
+            }
+            Ok(Event::Disconnected(addr)) => {
+                peers.streams.retain(|(a, _, _)| *a != addr);
+                // the connection dropped without a goodbye
+                if let Some(index) = users.iter().position(|user| user.peer == Peer::Tcp(addr)) {
+                    let user = users.remove(index);
+                    tracing::info!(user = %user.name, %addr, "connection dropped");
+                    peers.broadcast(&Packet::Leave { username: user.name, reason: LeaveReason::Timeout }.encode(), &users);
+                }
+                continue;
+            }
+            Ok(Event::Command(Command::Kick(name))) => {
+                kick(&peers, &mut users, &name);
+                continue;
+            }
+            Ok(Event::Command(Command::Ban(name))) => {
+                if let Some(user) = kick(&peers, &mut users, &name) {
+                    tracing::info!(user = %user.name, ip = %user.peer.ip(), "banned");
+                    banned.push(user.peer.ip());
+                }
+                continue;
+            }
+            Ok(Event::Command(Command::Topic(new))) => {
+                topic = new;
+                peers.broadcast(&Packet::Topic(topic.clone()).encode(), &users);
+                continue;
+            }
+            // exit signal
+            Ok(Event::Command(Command::Shutdown)) | Err(_) => return,
+        };
+
+        // drop everything from banned users
+        if banned.contains(&peer.ip()) {
+            continue;
+        }
+
+        // check if the user is already in the room
+        if let Some(user) = users.iter_mut().find(|user| user.peer == peer) {
+            user.heard = Instant::now();
+        } else {// user is not in the room yet
+            // the first thing a new user sends must be a join packet, ignore anything else
+            // so is a name longer than any client would send
+            let requested = match Packet::decode(&packet) {
+                Some(Packet::Join(name)) if name.len() <= MAX_USERNAME => name,
+                _ => continue
+            };
+            // two users with the same name couldn't be told apart, so the newcomer gets a suffix
+            let name = unique_name(&requested, &users.iter().map(|user| user.name.as_str()).collect::<Vec<_>>());
+            if name != requested {
+                peers.send(&Packet::Nick { old: requested, new: name.clone() }.encode(), peer);
+            }
+            tracing::info!(user = %name, addr = %peer.ip(), "joined");
+            // add him to the room
+            users.push(User { name: name.clone(), peer, away: false, heard: Instant::now() });
+            // send all usernames in the room to the new user
+            for user in users.iter() {
+                if user.peer == peer {// don't send the new user his own name
+                    continue;
+                }
+                // send message
+                peers.send(&Packet::Join(user.name.clone()).encode(), peer);
+                if user.away {
+                    peers.send(&Packet::Status { username: user.name.clone(), away: true }.encode(), peer);
+                }
+            }
+            if !topic.is_empty() {
+                peers.send(&Packet::Topic(topic.clone()).encode(), peer);
+            }
+            // announce the newcomer under the name they actually got
+            peers.broadcast(&Packet::Join(name).encode(), &users);
+            continue;
+        }
+
+        // leaves and status changes are announced under the name the sender joined
+        // with, so nobody can make someone else leave or go away. Only the server
+        // kicks or times anyone out, a user can only quit
+        match Packet::decode(&packet) {
+            Some(Packet::Leave { .. }) => {
+                if let Some(index) = users.iter().position(|user| user.peer == peer) {
+                    let user = users.remove(index);
+                    tracing::info!(user = %user.name, "left");
+                    peers.broadcast(&Packet::Leave { username: user.name, reason: LeaveReason::Quit }.encode(), &users);
+                }
+                continue;
+            }
+            Some(Packet::Status { away, .. }) => {
+                let status = match users.iter_mut().find(|user| user.peer == peer) {
+                    Some(user) => {
+                        user.away = away;
+                        Packet::Status { username: user.name.clone(), away }.encode()
                     }
+                    None => continue
+                };
+                peers.broadcast(&status, &users);
+                continue;
+            }
+            Some(Packet::Typing { typing, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&Packet::Typing { username: user.name.clone(), typing }.encode(), &users);
+                }
+                continue;
+            }
+            Some(Packet::Nick { new, .. }) => {
+                // two users with the same name couldn't be told apart, and long names are turned away like on joining
+                if users.iter().any(|user| user.name == new) || new.len() > MAX_USERNAME {
+                    // renamed back to what they are, or they'd keep waiting for it
+                    if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                        peers.send(&Packet::Nick { old: new, new: user.name.clone() }.encode(), peer);
+                    }
+                    continue;
+                }
+                let nick = match users.iter_mut().find(|user| user.peer == peer) {
+                    Some(user) => Packet::Nick { old: std::mem::replace(&mut user.name, new.clone()), new }.encode(),
+                    None => continue
+                };
+                peers.broadcast(&nick, &users);
+                continue;
+            }
+            // messages go out under the name the sender joined with, so nobody
+            // can post as someone else and then edit or unsend in their name
+            Some(Packet::Chat { message, sent, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&restamped(&packet, Packet::Chat { username: user.name.clone(), message, sent }), &users);
+                }
+                continue;
+            }
+            Some(Packet::Reply { message, to, quote, sent, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&restamped(&packet, Packet::Reply { username: user.name.clone(), message, to, quote, sent }), &users);
+                }
+                continue;
+            }
+            // so only the sender's own messages can be edited
+            Some(Packet::Edit { old, message, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&Packet::Edit { username: user.name.clone(), old, message }.encode(), &users);
                 }
-                
-                // send message to all users in the room
-                for user in users.iter() { // for each user
-                    // send message
-                    socket.send_to(&buffer[..size], user.addr).unwrap();
+                continue;
+            }
+            Some(Packet::Read { to, message, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&Packet::Read { username: user.name.clone(), to, message }.encode(), &users);
                 }
-                
-            },
-            Err(e) => panic!("Failed to read from connection: {}", e),
+                continue;
+            }
+            Some(Packet::Unsend { message, .. }) => {
+                if let Some(user) = users.iter().find(|user| user.peer == peer) {
+                    peers.broadcast(&Packet::Unsend { username: user.name.clone(), message }.encode(), &users);
+                }
+                continue;
+            }
+            // only the host sets the topic, through a command
+            Some(Packet::Topic(_)) => continue,
+            // only there to be heard
+            Some(Packet::Ping) => continue,
+            // only for whoever it's addressed to, and back to the sender so they know it arrived
+            Some(Packet::Private { to, message, .. }) => {
+                let Some(sender) = users.iter().find(|user| user.peer == peer) else { continue };
+                let recipients: Vec<Peer> = users.iter().filter(|user| user.peer == peer || to.contains(&user.name)).map(|user| user.peer).collect();
+                let packet = Packet::Private { username: sender.name.clone(), to, message }.encode();
+                for recipient in recipients {
+                    peers.send(&packet, recipient);
+                }
+                continue;
+            }
+            // someone already in the room joining again would add a made up
+            // user to everyone's list, and what doesn't decode isn't passed on
+            Some(Packet::Join(_)) | None => {}
+        }
+    }
+}
+
+/// Encodes a packet rebuilt from the one that came in, compressed again if
+/// that one was.
+fn restamped(original: &[u8], packet: Packet) -> Vec<u8> {
+    match original.first() {
+        Some(&DEFLATE) => packet.encode_compressed(),
+        _ => packet.encode()
+    }
+}
+
+/// Accepts TCP users and reads their frames on a thread per connection.
+fn accept(listener: TcpListener, eventtx: Sender<Event>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        let (addr, mut reader) = match (stream.peer_addr(), stream.try_clone()) {
+            (Ok(addr), Ok(reader)) => (addr, reader),
+            _ => continue
+        };
+        let _ = stream.set_nodelay(true);
+        if eventtx.send(Event::Connected(addr, stream)).is_err() {
+            return;
+        }
+        let tx = eventtx.clone();
+        thread::spawn(move || {
+            while let Ok(packet) = transport::read_frame(&mut reader) {
+                if tx.send(Event::Packet(Peer::Tcp(addr), packet)).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(Event::Disconnected(addr));
+        });
+    }
+}
+
+/// Returns the name as is if nobody has it yet, otherwise the first free
+/// "name(2)", "name(3)"... A long name is cut short to make room for the
+/// number, so the result is never longer than `MAX_USERNAME`.
+pub fn unique_name(name: &str, taken: &[&str]) -> String {
+    if !taken.contains(&name) {
+        return name.to_string();
+    }
+    (2..).map(|n| {
+        let suffix = format!("({})", n);
+        let mut end = name.len().min(MAX_USERNAME.saturating_sub(suffix.len()));
+        while !name.is_char_boundary(end) {
+            end -= 1;
         }
+        format!("{}{}", &name[..end], suffix)
+    }).find(|candidate| !taken.contains(&candidate.as_str())).unwrap()
+}
+
+/// Takes users nothing has come from in `timeout` out of the room. A client
+/// that crashed or lost its network never says goodbye, and over UDP nothing
+/// else would tell. They hear it too, in case it's only this way round that
+/// packets get lost.
+fn evict_silent(peers: &Peers, users: &mut Vec<User>, timeout: Duration) {
+    while let Some(index) = users.iter().position(|user| user.heard.elapsed() >= timeout) {
+        let user = users.remove(index);
+        tracing::info!(user = %user.name, "timed out");
+        let leave = Packet::Leave { username: user.name, reason: LeaveReason::Timeout }.encode();
+        peers.send(&leave, user.peer);
+        peers.broadcast(&leave, users);
     }
-}
\ No newline at end of file
+}
+
+/// Removes the user from the room and tells everyone, the kicked user included.
+fn kick(peers: &Peers, users: &mut Vec<User>, name: &str) -> Option<User> {
+    let index = users.iter().position(|user| user.name == name)?;
+    let kicked = users.remove(index);
+    let leave = Packet::Leave { username: kicked.name.clone(), reason: LeaveReason::Kicked }.encode();
+    peers.send(&leave, kicked.peer);
+    peers.broadcast(&leave, users);
+    Some(kicked)
+}
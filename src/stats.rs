@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// Traffic counters for the stats panel.
+#[derive(Default)]
+pub struct Stats {
+    pub packets_out: usize,
+    pub bytes_out: usize,
+    pub packets_in: usize,
+    pub bytes_in: usize,
+    // the time between sending our last delivered message and getting it back
+    pub rtt: Option<Duration>,
+}
+
+impl Stats {
+
+    pub fn sent(&mut self, bytes: usize) {
+        self.packets_out += 1;
+        self.bytes_out += bytes;
+    }
+
+    pub fn received(&mut self, bytes: usize) {
+        self.packets_in += 1;
+        self.bytes_in += bytes;
+    }
+
+    /// One line for the panel. Unreadable packets are counted by the UI
+    /// already, so they are passed in.
+    pub fn summary(&self, unreadable: usize) -> String {
+        let rtt = match self.rtt {
+            Some(rtt) => format!("{} ms", rtt.as_millis()),
+            None => "-".to_string()
+        };
+        format!("out {} packets, {} | in {} packets, {} | {} unreadable | rtt {}",
+            self.packets_out, bytes(self.bytes_out), self.packets_in, bytes(self.bytes_in), unreadable, rtt)
+    }
+}
+
+/// "512 B", "1.5 KiB", "3.2 MiB"
+pub fn bytes(count: usize) -> String {
+    match count {
+        0..1024 => format!("{} B", count),
+        1024..1048576 => format!("{:.1} KiB", count as f64 / 1024.0),
+        _ => format!("{:.1} MiB", count as f64 / 1048576.0)
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// One piece of the status line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Segment {
+    Connection,
+    Server,
+    Port,
+    Users,
+    Rtt,
+    Unread,
+    Time,
+}
+
+impl Segment {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "connection" => Some(Segment::Connection),
+            "server" => Some(Segment::Server),
+            "port" => Some(Segment::Port),
+            "users" => Some(Segment::Users),
+            "rtt" => Some(Segment::Rtt),
+            "unread" => Some(Segment::Unread),
+            "time" => Some(Segment::Time),
+            _ => None
+        }
+    }
+}
+
+/// Parses a comma separated list of segments, in the order they are shown.
+/// An empty list turns the status line off.
+pub fn parse(value: &str) -> Option<Vec<Segment>> {
+    value.split(',').map(str::trim).filter(|segment| !segment.is_empty()).map(Segment::parse).collect()
+}
+
+/// What the segments show.
+pub struct Info {
+    pub connected: bool,
+    pub users: usize,
+    // the room we're connected to, which changes when --servers fails over
+    pub server: String,
+    // the one we send from, picked by the system unless --port was given
+    pub port: Option<u16>,
+    pub rtt: Option<Duration>,
+    pub unread: usize,
+    pub time: String,
+}
+
+pub fn line(segments: &[Segment], info: &Info) -> String {
+    segments.iter().map(|segment| match segment {
+        Segment::Connection => if info.connected { "connected".to_string() } else { "connecting...".to_string() },
+        Segment::Server => info.server.clone(),
+        Segment::Port => match info.port {
+            Some(port) => format!("port {}", port),
+            None => "port -".to_string()
+        },
+        Segment::Users => if info.users == 1 { "1 user".to_string() } else { format!("{} users", info.users) },
+        Segment::Rtt => match info.rtt {
+            Some(rtt) => format!("rtt {} ms", rtt.as_millis()),
+            None => "rtt -".to_string()
+        },
+        Segment::Unread => format!("{} unread", info.unread),
+        Segment::Time => info.time.clone(),
+    }).collect::<Vec<_>>().join(" | ")
+}
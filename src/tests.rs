@@ -41,15 +41,13 @@ mod encryption {
         assert_eq!(decrypted, message);
     }
 
-    /// This is fails and had to be fixed
-    /// decrypt function has problems
     #[test]
     fn decrypt_invalid_cipher() {
-        let key = Key::<Aes256Gcm>::from_slice(&[0u8; 31]); // invalid key size
-        let cipher = Aes256Gcm::new(key);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[0u8; 32]));
+        let other = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[1u8; 32])); // someone else's key
         let message = "Hello, World!".to_string();
         let encrypted = encrypt(&cipher, message.clone());
-        assert!(decrypt(&cipher, &encrypted).is_err());
+        assert!(decrypt(&other, &encrypted).is_err());
     }
 
     #[test]
@@ -59,4 +57,1884 @@ mod encryption {
         let encrypted: [u8; 10] = [0u8; 10];
         assert_eq!(decrypt(&cipher, &encrypted).unwrap_err().to_string(), "Encrypted data too short");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decrypt_empty_and_one_byte_data() {
+        let key = Key::<Aes256Gcm>::from_slice(&[0u8; 32]);
+        let cipher = Aes256Gcm::new(key);
+        assert_eq!(decrypt(&cipher, &[]).unwrap_err().to_string(), "Encrypted data too short");
+        assert_eq!(decrypt(&cipher, &[0u8]).unwrap_err().to_string(), "Encrypted data too short");
+    }
+
+    #[test]
+    fn decrypt_nonce_without_tag() {
+        let key = Key::<Aes256Gcm>::from_slice(&[0u8; 32]);
+        let cipher = Aes256Gcm::new(key);
+        // a whole nonce but no room for the tag
+        assert_eq!(decrypt(&cipher, &[0u8; 27]).unwrap_err().to_string(), "Encrypted data too short");
+    }
+}
+#[cfg(test)]
+mod keywords {
+    use crate::{config::Config, notify::Keywords};
+
+    fn keywords(words: &[&str]) -> Keywords {
+        let mut keywords = Keywords::default();
+        for word in words {
+            keywords.add(word);
+        }
+        keywords
+    }
+
+    #[test]
+    fn find_whole_words_only() {
+        let keywords = keywords(&["cat"]);
+        assert_eq!(keywords.find("my cat, not the category"), vec![3..6]);
+        assert!(keywords.find("category").is_empty());
+    }
+
+    #[test]
+    fn find_substring_mode() {
+        let mut keywords = keywords(&["cat"]);
+        keywords.substring = true;
+        assert_eq!(keywords.find("category"), vec![0..3]);
+    }
+
+    #[test]
+    fn find_ignores_case() {
+        let keywords = keywords(&["Deploy"]);
+        assert_eq!(keywords.find("DEPLOY done"), vec![0..6]);
+    }
+
+    #[test]
+    fn remove_keyword() {
+        let mut keywords = keywords(&["cat", "dog"]);
+        assert!(keywords.remove("CAT"));
+        assert!(!keywords.remove("cat"));
+        assert_eq!(keywords.words, vec!["dog"]);
+    }
+
+    #[test]
+    fn config_notify_settings() {
+        let config = Config::parse("# hilights\nnotify = cat, dog\nnotify_mode = substring\nnotify_bell = off\n").unwrap();
+        assert_eq!(config.keywords.words, vec!["cat", "dog"]);
+        assert!(config.keywords.substring);
+        assert!(!config.keywords.bell);
+    }
+
+    #[test]
+    fn config_border() {
+        assert_eq!(Config::parse("border = rounded").unwrap().border, ratatui::symbols::border::ROUNDED);
+        assert_eq!(Config::default().border, ratatui::symbols::border::PLAIN);
+        assert!(Config::parse("border = wavy").is_err());
+    }
+
+    #[test]
+    fn config_history_limit() {
+        assert_eq!(Config::default().history_limit, 5000);
+        assert_eq!(Config::parse("history_limit = 200").unwrap().history_limit, 200);
+        assert!(Config::parse("history_limit = 0").is_err());
+    }
+
+    #[test]
+    fn config_confirm_quit() {
+        assert!(Config::default().confirm_quit);
+        assert!(!Config::parse("confirm_quit = off").unwrap().confirm_quit);
+        assert!(Config::parse("confirm_quit = maybe").is_err());
+    }
+
+    #[test]
+    fn config_gutter() {
+        assert!(!Config::default().gutter);
+        assert!(Config::parse("gutter = on").unwrap().gutter);
+    }
+
+    #[test]
+    fn config_page() {
+        use crate::config::Page;
+        assert_eq!(Config::default().page.rows(30), 10);
+        assert_eq!(Config::parse("page = full").unwrap().page.rows(30), 30);
+        assert_eq!(Config::parse("page = half").unwrap().page.rows(30), 15);
+        // a history one row tall still scrolls
+        assert_eq!(Page::Half.rows(1), 1);
+        assert_eq!(Config::parse("page = 3").unwrap().page, Page::Rows(3));
+        assert!(Config::parse("page = 0").is_err());
+        assert!(Config::parse("page = most").is_err());
+        assert_eq!(Config::parse("input_rows = 8").unwrap().input_rows, 8);
+        assert!(Config::parse("input_rows = 0").is_err());
+    }
+
+    #[test]
+    fn config_timestamps() {
+        use crate::config::Timestamps;
+        assert_eq!(Config::default().timestamps, Timestamps::Off);
+        assert_eq!(Config::parse("timestamps = right").unwrap().timestamps, Timestamps::Right);
+        assert!(Config::parse("timestamps = top").is_err());
+    }
+
+    #[test]
+    fn config_auto_scroll() {
+        use crate::config::Follow;
+        assert_eq!(Config::default().auto_scroll, Follow::Bottom);
+        assert_eq!(Config::parse("auto_scroll = never").unwrap().auto_scroll, Follow::Never);
+        assert!(Config::parse("auto_scroll = sometimes").is_err());
+        // at the bottom, and scrolled up
+        assert_eq!([Follow::Always.holds(0), Follow::Always.holds(5)], [false, false]);
+        assert_eq!([Follow::Bottom.holds(0), Follow::Bottom.holds(5)], [false, true]);
+        assert_eq!([Follow::Never.holds(0), Follow::Never.holds(5)], [true, true]);
+    }
+
+    #[test]
+    fn config_density() {
+        use crate::config::{Density, Separator, Timestamps};
+        // the preset wins, even over settings after it
+        let config = Config::parse("density = compact\ntimestamps = left\ngutter = true").unwrap();
+        assert_eq!(config.density, Some(Density::Compact));
+        assert_eq!((config.timestamps, config.gutter, config.merge_senders), (Timestamps::Off, false, true));
+        let config = Config::parse("density = comfortable").unwrap();
+        assert_eq!((config.separators, config.timestamps, config.gutter), (Separator::Line, Timestamps::Left, true));
+        // without one the settings are as written
+        assert!(Config::parse("gutter = true").unwrap().gutter);
+        assert!(Config::parse("density = cozy").is_err());
+    }
+
+    #[test]
+    fn config_separators() {
+        use std::time::Duration;
+        use crate::config::Separator;
+        let config = Config::parse("separators = line\nseparator_gap = 60").unwrap();
+        assert_eq!(config.separators, Separator::Line);
+        assert_eq!(config.separator_gap, Some(Duration::from_secs(60)));
+        assert_eq!(Config::parse("separator_gap = 0").unwrap().separator_gap, None);
+        assert_eq!(Config::default().separators, Separator::Off);
+        assert!(Config::parse("separators = dots").is_err());
+    }
+
+    #[test]
+    fn config_time_format() {
+        use crate::app::time_width;
+        let time = chrono::NaiveTime::from_hms_opt(15, 4, 0).unwrap();
+        for (format, shown, width) in [("%H:%M", "15:04", 5), ("%-I:%M %p", "3:04 PM", 8), ("%H.%M.%S", "15.04.00", 8)] {
+            let config = Config::parse(&format!("time_format = {}", format)).unwrap();
+            assert_eq!(time.format(&config.time_format).to_string(), shown);
+            assert_eq!(time_width(&config.time_format), width);
+        }
+        assert_eq!(Config::default().time_format, "%H:%M");
+        assert!(Config::parse("time_format = %H:%Q").is_err());
+        assert!(Config::parse("time_format = %").is_err());
+    }
+
+    #[test]
+    fn config_background() {
+        use ratatui::style::Color;
+        assert_eq!(Config::default().background, None);
+        assert_eq!(Config::parse("background = transparent").unwrap().background, None);
+        assert_eq!(Config::parse("background = black").unwrap().background, Some(Color::Black));
+        assert_eq!(Config::parse("background = #1e1e2e").unwrap().background, Some(Color::Rgb(0x1e, 0x1e, 0x2e)));
+        assert!(Config::parse("background = plaid").is_err());
+    }
+
+    #[test]
+    fn notify_levels_per_room() {
+        use crate::notify::{format_rooms, parse_rooms, Level};
+        let config = Config::parse("notify_level = all\nnotify_rooms = YWJj:muted, ZGVm== : mentions").unwrap();
+        assert_eq!(config.notify_level, Level::All);
+        assert_eq!(config.notify_rooms, [("YWJj".to_string(), Level::Muted), ("ZGVm==".to_string(), Level::Mentions)]);
+        assert_eq!(parse_rooms(&format_rooms(&config.notify_rooms)).unwrap(), config.notify_rooms);
+        assert_eq!(Config::default().notify_level, Level::Mentions);
+        assert!(Config::parse("notify_rooms = YWJj").is_err());
+        assert!(Config::parse("notify_rooms = YWJj:loud").is_err());
+    }
+
+    #[test]
+    fn saving_a_setting_keeps_the_rest() {
+        use crate::config::set;
+        let content = "# mine\nnotify_rooms = a:all\ngutter = true\nnotify_rooms = b:all\n";
+        assert_eq!(set(content, "notify_rooms", "c:muted"), "# mine\nnotify_rooms = a:all\ngutter = true\nnotify_rooms = c:muted\n");
+        assert_eq!(set("# notify_rooms = a:all", "notify_rooms", "c:muted"), "# notify_rooms = a:all\nnotify_rooms = c:muted\n");
+        assert_eq!(set("", "gutter", "true"), "gutter = true\n");
+    }
+
+    #[test]
+    fn config_send_on() {
+        assert!(!Config::default().send_on_ctrl_enter);
+        assert!(Config::parse("send_on = ctrl_enter").unwrap().send_on_ctrl_enter);
+        assert!(!Config::parse("send_on = enter").unwrap().send_on_ctrl_enter);
+        assert!(Config::parse("send_on = shift_enter").is_err());
+    }
+
+    #[test]
+    fn config_theme() {
+        use crate::theme::Theme;
+        assert_eq!(Config::default().theme, Theme::Default);
+        assert_eq!(Config::parse("theme = high-contrast").unwrap().theme, Theme::HighContrast);
+        assert!(Config::parse("theme = dark").is_err());
+    }
+
+    #[test]
+    fn filter_masks_whole_words() {
+        use crate::notify::mask;
+        let filter = Config::parse("filter = heck").unwrap().filter;
+        let message = "Heck, what the heck is heckling";
+        assert_eq!(mask(message, &filter.find(message)), "****, what the **** is heckling");
+        assert!(Config::default().filter.words.is_empty());
+        // one star per character, not per byte
+        let filter = Config::parse("filter = ünï").unwrap().filter;
+        assert_eq!(mask("ünï!", &filter.find("ünï!")), "***!");
+    }
+
+    #[test]
+    fn config_unknown_setting() {
+        assert!(Config::parse("colour = red").is_err());
+    }
+}
+
+#[cfg(test)]
+mod datagrams {
+    use std::net::UdpSocket;
+    use crate::protocol::recv_from;
+
+    fn pair() -> (UdpSocket, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        (sender, receiver)
+    }
+
+    #[test]
+    fn recv_datagram_that_fits() {
+        let (sender, receiver) = pair();
+        let mut buffer = [0u8; 17];
+        sender.send(&[1u8; 16]).unwrap();
+        assert_eq!(recv_from(&receiver, &mut buffer).unwrap().0, 16);
+    }
+
+    #[test]
+    fn recv_datagram_that_fills_buffer() {
+        let (sender, receiver) = pair();
+        let mut buffer = [0u8; 17];
+        sender.send(&[1u8; 17]).unwrap();
+        assert!(recv_from(&receiver, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn recv_oversized_datagram() {
+        let (sender, receiver) = pair();
+        let mut buffer = [0u8; 17];
+        sender.send(&[1u8; 100]).unwrap();
+        assert!(recv_from(&receiver, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn receiving_goes_on_after_a_drop() {
+        use std::io::ErrorKind;
+        let (sender, receiver) = pair();
+        let mut buffer = [0u8; 17];
+        sender.send(&[1u8; 100]).unwrap();
+        sender.send(&[2u8; 5]).unwrap();
+        assert_eq!(recv_from(&receiver, &mut buffer).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(recv_from(&receiver, &mut buffer).unwrap().0, 5);
+        assert_eq!(buffer[..5], [2u8; 5]);
+    }
+
+    #[test]
+    fn largest_datagram_fits() {
+        use crate::protocol::{buffer, MAX_DATAGRAM};
+        let (sender, receiver) = pair();
+        let mut buffer = buffer();
+        sender.send(&vec![1u8; MAX_DATAGRAM]).unwrap();
+        assert_eq!(recv_from(&receiver, &mut buffer).unwrap().0, MAX_DATAGRAM);
+    }
+}
+
+#[cfg(test)]
+mod join {
+    use rand::{Rng, RngCore};
+    use crate::protocol::parse_join;
+
+    #[test]
+    fn parse_valid_username() {
+        assert_eq!(parse_join(b"alice"), Some("alice".to_string()));
+    }
+
+    #[test]
+    fn parse_invalid_utf8() {
+        assert_eq!(parse_join(&[0xff, 0xfe, 0x41]), None);
+        assert_eq!(parse_join(&[0xc3]), None); // cut off in the middle of a character
+    }
+
+    #[test]
+    fn parse_empty_packet() {
+        assert_eq!(parse_join(&[]), None);
+    }
+
+    #[test]
+    fn parse_random_bytes() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10000 {
+            let mut packet = vec![0u8; rng.gen_range(0..12)];
+            rng.fill_bytes(&mut packet);
+            let parsed = parse_join(&packet);
+            assert_eq!(parsed.is_some(), !packet.is_empty() && std::str::from_utf8(&packet).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod packets {
+    use crate::protocol::{Packet, CHAT, DEFLATE, EDIT, JOIN, MAX_DATAGRAM, READ, REPLY, TOPIC, UNSEND};
+
+    #[test]
+    fn private_messages_keep_their_recipients() {
+        let packet = Packet::Private { username: "alice".to_string(), to: vec!["bob".to_string(), "carol|x".to_string()], message: "just | us".to_string() };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        assert_eq!(Packet::decode(&[crate::protocol::PRIVATE, 2, 0, 1, b'b']), None);
+    }
+
+    #[test]
+    fn chats_carry_when_they_were_sent() {
+        let packet = Packet::Chat { username: "alice".to_string(), message: "hi".to_string(), sent: crate::protocol::now() };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        // without the time it's not a chat packet
+        assert_eq!(Packet::decode(b"\x02bob|hi"), None);
+    }
+
+    #[test]
+    fn short_chat_is_not_a_join() {
+        let packet = Packet::Chat { username: "a".to_string(), message: "hi".to_string(), sent: 0 };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+
+    #[test]
+    fn long_username_is_still_a_join() {
+        let packet = Packet::Join("a_very_long_username".to_string());
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+
+    #[test]
+    fn untagged_packets_are_dropped() {
+        assert_eq!(Packet::decode(b"alice"), None);
+        assert_eq!(Packet::decode(b"alice|hello there"), None);
+        assert_eq!(Packet::decode(&[]), None);
+    }
+
+    #[test]
+    fn chat_without_a_separator_is_dropped() {
+        use aes_gcm::{Aes256Gcm, Key, KeyInit};
+        use crate::crypt::{decrypt, encrypt};
+        // a peer with the right key but a broken client
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[7u8; 32]));
+        let decrypted = decrypt(&cipher, &encrypt(&cipher, "no separator here".to_string())).unwrap();
+        let mut packet = vec![CHAT];
+        packet.extend_from_slice(decrypted.as_bytes());
+        assert_eq!(Packet::decode(&packet), None);
+        for tag in [REPLY, EDIT, UNSEND] {
+            assert_eq!(Packet::decode(&[tag, 0, 0, 0, 0, b'a', b'b']), None);
+        }
+    }
+
+    #[test]
+    fn long_messages_are_compressed() {
+        let short = Packet::Chat { username: "alice".to_string(), message: "hi".to_string(), sent: 0 };
+        assert_eq!(short.encode_compressed(), short.encode());
+
+        let long = Packet::Chat { username: "alice".to_string(), message: "all work and no play ".repeat(50), sent: 0 };
+        let compressed = long.encode_compressed();
+        assert_eq!(compressed[0], DEFLATE);
+        assert!(compressed.len() < long.encode().len() / 4);
+        assert_eq!(Packet::decode(&compressed), Some(long));
+    }
+
+    #[test]
+    fn compressed_packets_stay_small() {
+        use flate2::{write::DeflateEncoder, Compression};
+        use std::io::Write;
+        let deflate = |bytes: &[u8]| {
+            let mut encoder = DeflateEncoder::new(vec![DEFLATE], Compression::best());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        };
+        // a few hundred bytes that would inflate past any datagram
+        let mut bomb = vec![CHAT];
+        bomb.extend_from_slice(b"a|");
+        bomb.resize(MAX_DATAGRAM * 16, b'a');
+        assert_eq!(Packet::decode(&deflate(&bomb)), None);
+
+        // and no packet in a packet in a packet
+        let inner = Packet::Chat { username: "a".to_string(), message: "b".to_string(), sent: 0 };
+        assert_eq!(Packet::decode(&deflate(&deflate(&inner.encode()))), None);
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(Packet::Join("alice".to_string()).encode()[0], JOIN);
+        assert_eq!(Packet::Chat { username: "alice".to_string(), message: String::new(), sent: 0 }.encode()[0], CHAT);
+    }
+
+    #[test]
+    fn reply_roundtrip() {
+        let packet = Packet::Reply { username: "bob".to_string(), message: "yes | no".to_string(), to: "alice".to_string(), quote: "lunch? | dinner?".to_string(), sent: 1_700_000_000_000 };
+        assert_eq!(packet.encode()[0], REPLY);
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        // lengths running past the end of the packet
+        assert_eq!(Packet::decode(&[REPLY, 0, 9, b'a']), None);
+        assert_eq!(Packet::decode(&[REPLY, 0, 1, b'a', 0]), None);
+    }
+
+    #[test]
+    fn edit_roundtrip() {
+        let packet = Packet::Edit { username: "bob".to_string(), old: "teh | cat".to_string(), message: "the\ncat".to_string() };
+        assert_eq!(packet.encode()[0], EDIT);
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        assert_eq!(Packet::decode(&[EDIT, 0, 0, b'b', b'o', b'b']), None);
+    }
+
+    #[test]
+    fn unsend_roundtrip() {
+        let packet = Packet::Unsend { username: "bob".to_string(), message: "oops | sorry".to_string() };
+        assert_eq!(packet.encode()[0], UNSEND);
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        assert_eq!(Packet::decode(&[UNSEND, b'b', b'o', b'b']), None);
+    }
+
+    #[test]
+    fn read_roundtrip() {
+        let packet = Packet::Read { username: "bob".to_string(), to: "alice".to_string(), message: "lunch? | dinner?".to_string() };
+        assert_eq!(packet.encode()[0], READ);
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        // nobody read it
+        assert_eq!(Packet::decode(&[READ, 0, 1, b'a', 0, 0]), None);
+    }
+
+    #[test]
+    fn quotes_are_cut_short() {
+        use crate::app::quote;
+        assert_eq!(quote("lunch?"), "lunch?");
+        assert_eq!(quote("first line\nsecond"), "first line…");
+        assert_eq!(quote(&"é".repeat(50)), "é".repeat(40) + "…");
+    }
+
+    #[test]
+    fn topic_roundtrip() {
+        for topic in ["", "planning | friday"] {
+            let packet = Packet::Topic(topic.to_string());
+            assert_eq!(packet.encode()[0], TOPIC);
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+        assert_eq!(Packet::decode(&[TOPIC, 0xff]), None);
+    }
+}
+
+#[cfg(test)]
+mod leave {
+    use crate::protocol::{LeaveReason, Packet, LEAVE};
+
+    #[test]
+    fn leave_roundtrip() {
+        for reason in [LeaveReason::Quit, LeaveReason::Timeout, LeaveReason::Kicked] {
+            let packet = Packet::Leave { username: "alice".to_string(), reason };
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn leave_with_unknown_reason() {
+        assert_eq!(Packet::decode(&[LEAVE, 9, b'a']), None);
+        assert_eq!(Packet::decode(&[LEAVE]), None);
+    }
+
+    #[test]
+    fn kicked_clients_stop_sending() {
+        use ratatui::{buffer::Buffer, crossterm::event::{KeyCode, KeyEvent, KeyModifiers}, layout::Rect, widgets::Widget};
+        use crate::{app::App, config::Config};
+        let mut app = App::offline("alice".to_string(), Config::default());
+        app.handle_packet(Packet::Leave { username: "alice".to_string(), reason: LeaveReason::Kicked });
+        for key in "hi".chars().map(KeyCode::Char).chain([KeyCode::Enter]) {
+            app.handle_key_event(KeyEvent::new(key, KeyModifiers::NONE));
+        }
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 80, 24));
+        (&app).render(buf.area, &mut buf);
+        let rows: Vec<String> = (0..24).map(|y| (0..80).map(|x| buf[(x, y)].symbol()).collect()).collect();
+        assert!(rows.iter().any(|row| row.contains("You were kicked from the room")), "{:?}", rows);
+        assert!(rows.iter().any(|row| row.contains("Failed to send the message: you were kicked")), "{:?}", rows);
+    }
+}
+
+#[cfg(test)]
+mod presence {
+    use std::time::{Duration, Instant};
+    use crate::{presence::{summarize, Change, Presence}, protocol::LeaveReason};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn summarize_names() {
+        assert_eq!(summarize(&names(&["Alice"])), "Alice");
+        assert_eq!(summarize(&names(&["Alice", "Bob"])), "Alice and Bob");
+        assert_eq!(summarize(&names(&["Alice", "Bob", "Carol"])), "Alice, Bob and Carol");
+        assert_eq!(summarize(&names(&["Alice", "Bob", "Carol", "Dave"])), "Alice, Bob and 2 others");
+    }
+
+    #[test]
+    fn burst_is_held_back_then_grouped() {
+        let start = Instant::now();
+        let mut presence = Presence::default();
+        presence.push(Change::Joined, "Alice".to_string(), start);
+        presence.push(Change::Left(LeaveReason::Quit), "Carol".to_string(), start);
+        presence.push(Change::Joined, "Bob".to_string(), start + Duration::from_millis(300));
+        assert!(presence.flush(start + Duration::from_millis(500)).is_empty());
+
+        let groups = presence.flush(start + Duration::from_secs(1));
+        assert_eq!(groups, vec![
+            (Change::Joined, names(&["Alice", "Bob"])),
+            (Change::Left(LeaveReason::Quit), names(&["Carol"])),
+        ]);
+        assert!(presence.flush(start + Duration::from_secs(5)).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod status {
+    use std::time::Duration;
+    use crate::{config::Config, protocol::{Packet, STATUS}};
+
+    #[test]
+    fn status_roundtrip() {
+        for away in [true, false] {
+            let packet = Packet::Status { username: "alice".to_string(), away };
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+        assert_eq!(Packet::decode(&[STATUS, 7, b'a']), None);
+    }
+
+    #[test]
+    fn config_away_after() {
+        assert_eq!(Config::default().away_after, Some(Duration::from_secs(300)));
+        assert_eq!(Config::parse("away_after = 60").unwrap().away_after, Some(Duration::from_secs(60)));
+        assert_eq!(Config::parse("away_after = 0").unwrap().away_after, None);
+    }
+}
+
+#[cfg(test)]
+mod nick {
+    use crate::protocol::{Packet, NICK};
+
+    #[test]
+    fn nick_roundtrip() {
+        let packet = Packet::Nick { old: "alice".to_string(), new: "alice|away".to_string() };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+
+    #[test]
+    fn nick_with_bad_length() {
+        assert_eq!(Packet::decode(&[NICK, 0, 9, b'a', b'b']), None);
+        assert_eq!(Packet::decode(&[NICK, 0]), None);
+        assert_eq!(Packet::decode(&[NICK, 0, 1, b'a']), None); // no new name
+    }
+
+    #[test]
+    fn refused_renames_are_told() {
+        use ratatui::{buffer::Buffer, crossterm::event::{KeyCode, KeyEvent, KeyModifiers}, layout::Rect, widgets::Widget};
+        use crate::{app::App, config::Config};
+        let mut app = App::offline("alice".to_string(), Config::default());
+        for key in "/nick carol".chars().map(KeyCode::Char).chain([KeyCode::Enter]) {
+            app.handle_key_event(KeyEvent::new(key, KeyModifiers::NONE));
+        }
+        app.handle_packet(Packet::Nick { old: "carol".to_string(), new: "alice".to_string() });
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 120, 24));
+        (&app).render(buf.area, &mut buf);
+        let rows: Vec<String> = (0..24).map(|y| (0..120).map(|x| buf[(x, y)].symbol()).collect()).collect();
+        assert!(rows.iter().any(|row| row.contains("The room didn't let you be carol")), "{:?}", rows);
+        assert!(!rows.iter().any(|row| row.contains("is now")), "{:?}", rows);
+    }
+}
+
+#[cfg(test)]
+mod collisions {
+    use crate::server::unique_name;
+
+    #[test]
+    fn free_name_is_kept() {
+        assert_eq!(unique_name("alice", &["bob"]), "alice");
+    }
+
+    #[test]
+    fn taken_name_gets_a_suffix() {
+        assert_eq!(unique_name("alice", &["alice", "bob"]), "alice(2)");
+        assert_eq!(unique_name("alice", &["alice", "alice(2)"]), "alice(3)");
+    }
+
+    #[test]
+    fn suffixed_names_still_fit() {
+        use crate::protocol::MAX_USERNAME;
+        let long = "a".repeat(MAX_USERNAME);
+        let renamed = unique_name(&long, &[&long]);
+        assert_eq!(renamed, "a".repeat(MAX_USERNAME - 3) + "(2)");
+        // cut between characters, not inside one
+        let wide = "é".repeat(MAX_USERNAME / 2);
+        let renamed = unique_name(&wide, &[&wide]);
+        assert!(renamed.len() <= MAX_USERNAME);
+        assert_eq!(renamed, "é".repeat(MAX_USERNAME / 2 - 2) + "(2)");
+    }
+}
+
+#[cfg(test)]
+mod transports {
+    use std::{io::Cursor, net::{TcpListener, UdpSocket}, thread, time::Duration};
+    use crate::transport::{self, read_frame, write_frame, Kind};
+
+    #[test]
+    fn frame_roundtrip() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"hello").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+        let mut stream = Cursor::new(stream);
+        assert_eq!(read_frame(&mut stream).unwrap(), b"hello");
+        assert_eq!(read_frame(&mut stream).unwrap(), b"");
+        assert!(read_frame(&mut stream).is_err());
+    }
+
+    #[test]
+    fn a_taken_port_says_who_has_it() {
+        use std::{io::ErrorKind, net::{IpAddr, Ipv4Addr}};
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let Err(e) = transport::bind(Kind::Udp, IpAddr::V4(Ipv4Addr::LOCALHOST), &port.to_string()) else { panic!("bound a port that's in use") };
+        assert_eq!(e.kind(), ErrorKind::AddrInUse);
+        assert!(e.to_string().contains(&format!("Port {}", port)), "{}", e);
+        // the test is the one holding it, so it looks like another Blossom
+        if cfg!(target_os = "linux") {
+            assert!(e.to_string().contains("another Blossom"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn frame_too_big() {
+        let mut stream = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        assert!(read_frame(&mut stream).is_err());
+    }
+
+    #[test]
+    fn offline_echoes() {
+        use crate::{protocol::Packet, transport::{LocalTransport, Transport, ECHO}};
+        let mut local = LocalTransport::default();
+        local.connect("offline").unwrap();
+        let chat = Packet::Chat { username: "alice".to_string(), message: "hi".to_string(), sent: 0 };
+        local.send(&Packet::Join("alice".to_string()).encode()).unwrap();
+        local.send(&chat.encode()).unwrap();
+        let mut received = Vec::new();
+        while let Some(packet) = local.try_recv().unwrap() {
+            received.push(Packet::decode(&packet).unwrap());
+        }
+        assert_eq!(received, vec![
+            Packet::Join("alice".to_string()),
+            Packet::Join(ECHO.to_string()),
+            chat,
+            Packet::Chat { username: ECHO.to_string(), message: "hi".to_string(), sent: 0 },
+        ]);
+        assert!(local.local_addr().is_err());
+    }
+
+    fn recv(transport: &mut Box<dyn transport::Transport>) -> Vec<u8> {
+        for _ in 0..100 {
+            if let Some(packet) = transport.try_recv().unwrap() {
+                return packet;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("nothing arrived");
+    }
+
+    #[test]
+    fn tcp_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let packet = read_frame(&mut stream).unwrap();
+            write_frame(&mut stream, &packet).unwrap();
+        });
+
+        let mut transport = transport::bind(Kind::Tcp, "::".parse().unwrap(), "0").unwrap();
+        assert!(transport.send(b"early").is_err());
+        assert!(transport.local_addr().is_err());
+        transport.connect(&addr).unwrap();
+        transport.send(b"echo").unwrap();
+        assert_eq!(recv(&mut transport), b"echo");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn group_addresses() {
+        use crate::transport::is_group;
+        assert!(is_group("ff02::1234".parse().unwrap()));
+        assert!(is_group("239.1.2.3".parse().unwrap()));
+        assert!(is_group("255.255.255.255".parse().unwrap()));
+        assert!(!is_group("200:1234::1".parse().unwrap()));
+        assert!(!is_group("192.168.1.255".parse().unwrap()));
+        assert!(!is_group("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn udp_roundtrip() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut transport = transport::bind(Kind::Udp, "127.0.0.1".parse().unwrap(), "0").unwrap();
+        transport.connect(&server.local_addr().unwrap().to_string()).unwrap();
+        transport.send(b"echo").unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (size, addr) = server.recv_from(&mut buffer).unwrap();
+        server.send_to(&buffer[..size], addr).unwrap();
+        assert_eq!(transport.local_addr().unwrap(), addr);
+        assert_eq!(recv(&mut transport), b"echo");
+    }
+}
+
+#[cfg(test)]
+mod history {
+    use ratatui::text::Line;
+    use crate::app::{ellipsize, history_view, input_rows, pin_row, reflow_scroll, stamp_row, visible_lines, visible_rows, written};
+
+    #[test]
+    fn times_from_clocks_that_are_off_are_not_shown() {
+        use chrono::{Duration, Local, TimeZone};
+        let now = 1_700_000_000_000_u64;
+        let received = Local.timestamp_millis_opt(now as i64).unwrap();
+        assert_eq!(written(now - 4000, received), (received - Duration::seconds(4), false));
+        // a second ahead is only a clock a little off, and shows as now
+        assert_eq!(written(now + 1000, received), (received, false));
+        for skewed in [now + 60_000, now - 3_600_000, 0, u64::MAX] {
+            assert_eq!(written(skewed, received), (received, true));
+        }
+    }
+
+    fn lines(count: usize) -> Vec<Line<'static>> {
+        (0..count).map(|i| Line::from(i.to_string())).collect()
+    }
+
+    fn shown(lines: Vec<Line>) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn newest_lines_when_not_scrolled() {
+        assert_eq!(shown(visible_lines(&lines(10), 80, 3, 0)), vec!["7", "8", "9"]);
+    }
+
+    #[test]
+    fn everything_when_it_fits() {
+        assert_eq!(shown(visible_lines(&lines(2), 80, 5, 0)), vec!["0", "1"]);
+    }
+
+    #[test]
+    fn scrolled_up() {
+        assert_eq!(shown(visible_lines(&lines(10), 80, 3, 4)), vec!["3", "4", "5"]);
+    }
+
+    #[test]
+    fn scrolled_past_the_top() {
+        assert_eq!(shown(visible_lines(&lines(10), 80, 3, 50)), vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn long_lines_take_several_rows() {
+        let history = vec![Line::from("old"), Line::from("aaaa bbbb cccc")];
+        assert_eq!(shown(visible_lines(&history, 9, 2, 0)), vec!["aaaa bbbb", "cccc"]);
+        assert_eq!(shown(visible_lines(&history, 9, 2, 1)), vec!["old", "aaaa bbbb"]);
+    }
+
+    #[test]
+    fn resizing_keeps_the_same_message_in_view() {
+        let history: Vec<Line> = (0..10).map(|i| Line::from(format!("message {} ends {}", i, i))).collect();
+        assert_eq!(shown(visible_lines(&history, 80, 3, 5)).last().unwrap(), "message 4 ends 4");
+
+        let scroll = reflow_scroll(&history, 80, 10, 5);
+        assert_eq!(shown(visible_lines(&history, 10, 3, scroll)), vec!["ends 3", "message 4", "ends 4"]);
+        // and back again
+        assert_eq!(reflow_scroll(&history, 10, 80, scroll), 5);
+    }
+
+    #[test]
+    fn resizing_within_a_long_message() {
+        let history = vec![Line::from("aa bb cc dd"), Line::from("new")];
+        // "new" and the last row of the long message are below the view at width 2
+        assert_eq!(reflow_scroll(&history, 2, 5, 3), 2);
+        assert_eq!(reflow_scroll(&history, 2, 80, 3), 1);
+    }
+
+    #[test]
+    fn times_line_up_on_the_right() {
+        assert_eq!(stamp_row(Line::from("[a] hi"), 10, "12:00").to_string(), "[a] hi     12:00");
+        // wide characters take two cells
+        assert_eq!(stamp_row(Line::from("[a] 日本"), 10, "12:00").to_string(), "[a] 日本   12:00");
+        let history = [Line::from("aaaa bbbb"), Line::from("cc")];
+        let firsts: Vec<Option<usize>> = visible_rows(&history, 4, 5, 0).into_iter().map(|(index, _)| index).collect();
+        assert_eq!(firsts, [Some(0), None, Some(1)]);
+    }
+
+    #[test]
+    fn panels_that_dont_fit_are_left_out() {
+        use ratatui::{buffer::Buffer, crossterm::event::{KeyCode, KeyEvent, KeyModifiers}, layout::Rect, widgets::Widget};
+        use crate::{app::App, config::Config};
+        // F2 saves whether the key is shown, that shouldn't touch the real config
+        let dir = std::env::temp_dir().join(format!("blossom-panels-{}", std::process::id()));
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        let mut app = App::offline("alice".to_string(), Config::default());
+        // the room key, the address and the stats, three rows each
+        for key in [KeyCode::F(2), KeyCode::F(3)].into_iter().chain("/stats".chars().map(KeyCode::Char)).chain([KeyCode::Enter]) {
+            app.handle_key_event(KeyEvent::new(key, KeyModifiers::NONE));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 80, 5));
+        (&app).render(buf.area, &mut buf);
+        let rows: Vec<String> = (0..5).map(|y| (0..80).map(|x| buf[(x, y)].symbol()).collect()).collect();
+        assert!(rows[0].contains("Room Key"), "{:?}", rows);
+        assert!(!rows.iter().any(|row| row.contains("Address") || row.contains("Stats")), "{:?}", rows);
+    }
+
+    #[test]
+    fn empty_history() {
+        use ratatui::{buffer::Buffer, layout::Rect, widgets::{Paragraph, Widget}};
+        assert!(visible_lines(&[], 20, 5, 0).is_empty());
+        assert!(visible_lines(&[], 20, 5, 3).is_empty());
+        assert_eq!(reflow_scroll(&[], 20, 10, 4), 0);
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 20, 5));
+        Paragraph::new(history_view(&[], 20, 5, 0)).render(buf.area, &mut buf);
+        let rows: Vec<String> = (0..5).map(|y| (0..20).map(|x| buf[(x, y)].symbol()).collect()).collect();
+        assert_eq!(rows[2], "   No messages yet  ");
+        assert!(rows.iter().enumerate().all(|(y, row)| y == 2 || row.trim().is_empty()));
+        // even without room to center it
+        assert_eq!(history_view(&[], 20, 0, 0).len(), 1);
+    }
+
+    #[test]
+    fn long_names_are_cut_short() {
+        assert_eq!(ellipsize("alice", 16), "alice");
+        assert_eq!(ellipsize("abcdefghij", 10), "abcdefghij");
+        assert_eq!(ellipsize("abcdefghijk", 10), "abcdefghi…");
+        // wide characters count as two cells
+        assert_eq!(ellipsize("日本語の名前", 6), "日本…");
+        assert_eq!(ellipsize("abcdefghijk", 0), "abcdefghijk");
+    }
+
+    #[test]
+    fn pinned_message_fits_one_row() {
+        assert_eq!(pin_row("alice", "line one\nline two", 20).to_string(), "pinned alice: line …");
+        assert_eq!(pin_row("bob", "hi", 20).to_string(), "pinned bob: hi");
+    }
+
+    #[test]
+    fn user_colors_stay_the_same() {
+        use crate::app::user_color;
+        assert_eq!(user_color("alice"), user_color("alice"));
+        assert!(["alice", "bob", "carol", "dave", "erin", "frank"].iter().any(|name| user_color(name) != user_color("alice")));
+    }
+
+    #[test]
+    fn input_grows_with_the_text() {
+        let (rows, cursor, _) = input_rows("", 10, 5, 0);
+        assert_eq!(shown(rows), vec![""]);
+        assert_eq!(cursor, Some((0, 0)));
+
+        let (rows, cursor, _) = input_rows("hello big world", 10, 5, 0);
+        assert_eq!(shown(rows), vec!["hello big", "world"]);
+        assert_eq!(cursor, Some((5, 1)));
+
+        let (rows, cursor, _) = input_rows("one\ntwo", 10, 5, 0);
+        assert_eq!(shown(rows), vec!["one", "two"]);
+        assert_eq!(cursor, Some((3, 1)));
+    }
+
+    #[test]
+    fn input_cursor_after_a_full_row() {
+        let (rows, cursor, _) = input_rows("abcd", 4, 5, 0);
+        assert_eq!(shown(rows), vec!["abcd", ""]);
+        assert_eq!(cursor, Some((0, 1)));
+    }
+
+    #[test]
+    fn input_keeps_the_last_rows() {
+        let (rows, cursor, _) = input_rows("1\n2\n3\n4", 10, 2, 0);
+        assert_eq!(shown(rows), vec!["3", "4"]);
+        assert_eq!(cursor, Some((1, 1)));
+    }
+
+    #[test]
+    fn input_scrolls_back_over_a_draft() {
+        let (rows, cursor, scroll) = input_rows("1\n2\n3\n4", 10, 2, 1);
+        assert_eq!(shown(rows), vec!["2", "3"]);
+        assert_eq!((cursor, scroll), (None, 1));
+        // no further than the first row
+        let (rows, _, scroll) = input_rows("1\n2\n3\n4", 10, 2, 9);
+        assert_eq!(shown(rows), vec!["1", "2"]);
+        assert_eq!(scroll, 2);
+        // nothing to scroll when it all fits
+        assert_eq!(input_rows("1\n2", 10, 2, 3).1, Some((1, 1)));
+    }
+
+    #[test]
+    fn no_room_at_all() {
+        assert!(visible_lines(&lines(10), 80, 0, 0).is_empty());
+        assert!(visible_lines(&lines(0), 80, 3, 2).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod emoji {
+    use crate::emoji::expand;
+
+    #[test]
+    fn expand_known_shortcodes() {
+        assert_eq!(expand("hi :wave: :smile:"), "hi 👋 😄");
+        assert_eq!(expand(":+1::fire:"), "👍🔥");
+    }
+
+    #[test]
+    fn unknown_shortcodes_stay_literal() {
+        assert_eq!(expand(":notanemoji: and :"), ":notanemoji: and :");
+        assert_eq!(expand("at 12:30:45"), "at 12:30:45");
+    }
+
+    #[test]
+    fn closing_colon_can_open_the_next_one() {
+        assert_eq!(expand("ratio:smile:"), "ratio😄");
+        assert_eq!(expand("a:b:smile:"), "a:b😄");
+    }
+}
+
+#[cfg(test)]
+mod wrapping {
+    use ratatui::{style::Stylize, text::Line};
+    use crate::wrap::{collapse_lines, row_count, split_lines, wrap};
+
+    fn rows(line: &Line, width: usize) -> Vec<String> {
+        wrap(line, width).iter().map(|row| row.to_string()).collect()
+    }
+
+    #[test]
+    fn wrap_at_spaces() {
+        assert_eq!(rows(&Line::from("hello big world"), 10), vec!["hello big", "world"]);
+    }
+
+    #[test]
+    fn wrap_long_word() {
+        assert_eq!(rows(&Line::from("abcdefgh"), 3), vec!["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn wrap_cjk_by_cells() {
+        // every character is two cells wide
+        assert_eq!(rows(&Line::from("日本語です"), 5), vec!["日本", "語で", "す"]);
+        assert_eq!(rows(&Line::from("日本語です"), 4), vec!["日本", "語で", "す"]);
+        assert_eq!(row_count(&Line::from("日本語です"), 10), 1);
+    }
+
+    #[test]
+    fn wrap_mixed_width() {
+        assert_eq!(rows(&Line::from("ab日本cd"), 5), vec!["ab日", "本cd"]);
+        assert_eq!(rows(&Line::from("hi 😄😄 ok"), 6), vec!["hi", "😄😄", "ok"]);
+        assert_eq!(rows(&Line::from("😄😄😄😄"), 5), vec!["😄😄", "😄😄"]);
+    }
+
+    #[test]
+    fn wrap_keeps_span_styles() {
+        let line = Line::from(vec!["[alice] ".cyan(), "hello there".gray()]);
+        let wrapped = wrap(&line, 14);
+        assert_eq!(wrapped.len(), 2);
+        assert_eq!(wrapped[0].spans[0].style, line.spans[0].style);
+        assert_eq!(wrapped[0].spans[1].content, "hello");
+        assert_eq!(wrapped[1].spans[0].content, "there");
+        assert_eq!(wrapped[1].spans[0].style, line.spans[1].style);
+    }
+
+    #[test]
+    fn wrap_empty_line() {
+        assert_eq!(rows(&Line::from(""), 10), vec![""]);
+    }
+
+    #[test]
+    fn split_at_newlines() {
+        let lines = split_lines(vec!["one\ntw".gray(), "o\n".yellow()]);
+        let text: Vec<String> = lines.iter().map(|spans| Line::from(spans.clone()).to_string()).collect();
+        assert_eq!(text, vec!["one", "two", ""]);
+        assert_eq!(lines[1][1].style, "o".yellow().style);
+    }
+
+    #[test]
+    fn compact_newlines() {
+        let spans = collapse_lines(vec!["one\ntw".gray(), "o".yellow()]);
+        assert_eq!(Line::from(spans.clone()).to_string(), "one ⏎ two");
+        assert_eq!(spans[1].style, "o".yellow().style);
+    }
+}
+
+#[cfg(test)]
+mod delivery {
+    use std::time::{Duration, Instant};
+    use crate::delivery::{Kind, Outbox};
+
+    #[test]
+    fn echoes_match_the_oldest_same_message() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.sent(3, Kind::Chat, "hi".to_string(), now);
+        outbox.sent(4, Kind::Chat, "hi".to_string(), now);
+        outbox.sent(5, Kind::Chat, "bye".to_string(), now);
+        assert_eq!(outbox.echoed(&Kind::Chat, "bye"), Some((5, now)));
+        assert_eq!(outbox.echoed(&Kind::Chat, "hi"), Some((3, now)));
+        assert_eq!(outbox.echoed(&Kind::Chat, "hi"), Some((4, now)));
+        assert_eq!(outbox.echoed(&Kind::Chat, "hi"), None);
+        assert_eq!(outbox.len(), 0);
+    }
+
+    #[test]
+    fn edits_above_move_pending_messages() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.sent(2, Kind::Chat, "above".to_string(), now);
+        outbox.sent(6, Kind::Chat, "below".to_string(), now);
+        // the message at line 3 grew by a line
+        outbox.shift(3, 1);
+        assert_eq!(outbox.echoed(&Kind::Chat, "below"), Some((7, now)));
+        assert_eq!(outbox.echoed(&Kind::Chat, "above"), Some((2, now)));
+    }
+
+    #[test]
+    fn unanswered_messages_expire() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.sent(0, Kind::Chat, "lost".to_string(), now);
+        outbox.sent(1, Kind::Chat, "later".to_string(), now + Duration::from_secs(3));
+        assert!(outbox.expired(now + Duration::from_secs(1)).is_empty());
+        assert_eq!(outbox.expired(now + Duration::from_secs(5)), vec![0]);
+        assert_eq!(outbox.len(), 1);
+        assert!(outbox.expired(now + Duration::from_secs(6)).is_empty());
+        assert_eq!(outbox.echoed(&Kind::Chat, "later"), Some((1, now + Duration::from_secs(3))));
+    }
+
+    #[test]
+    fn late_echoes_still_find_their_message() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.sent(0, Kind::Chat, "slow".to_string(), now);
+        outbox.sent(1, Kind::Chat, "gone".to_string(), now);
+        assert_eq!(outbox.expired(now + Duration::from_secs(5)), vec![0, 1]);
+        assert!(outbox.is_empty());
+        // a waiting message with the same text is matched before a timed out one
+        outbox.sent(2, Kind::Chat, "slow".to_string(), now + Duration::from_secs(6));
+        assert_eq!(outbox.echoed(&Kind::Chat, "slow"), Some((2, now + Duration::from_secs(6))));
+        assert_eq!(outbox.echoed(&Kind::Chat, "slow"), Some((0, now)));
+        // and after a minute it's only a message that looks the same
+        assert!(outbox.expired(now + Duration::from_secs(60)).is_empty());
+        assert_eq!(outbox.echoed(&Kind::Chat, "gone"), None);
+    }
+
+    #[test]
+    fn echoes_match_what_they_were_sent_as() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        let private = Kind::Private(vec!["bob".to_string()]);
+        let reply = Kind::Reply("bob".to_string(), "hey".to_string());
+        outbox.sent(0, Kind::Chat, "hi".to_string(), now);
+        outbox.sent(1, private.clone(), "hi".to_string(), now);
+        outbox.sent(2, reply.clone(), "hi".to_string(), now);
+        assert_eq!(outbox.echoed(&Kind::Private(vec!["carol".to_string()]), "hi"), None);
+        assert_eq!(outbox.echoed(&reply, "hi"), Some((2, now)));
+        assert_eq!(outbox.echoed(&private, "hi"), Some((1, now)));
+        assert_eq!(outbox.echoed(&reply, "hi"), None);
+        assert_eq!(outbox.echoed(&Kind::Chat, "hi"), Some((0, now)));
+    }
+
+    #[test]
+    fn repeated_messages_are_edited_and_unsent_newest_first() {
+        use ratatui::{buffer::Buffer, crossterm::event::{KeyCode, KeyEvent, KeyModifiers}, layout::Rect, widgets::Widget};
+        use crate::{app::App, config::Config, protocol::Packet};
+        let mut app = App::offline("alice".to_string(), Config::default());
+        for _ in 0..3 {
+            for key in "hi".chars().map(KeyCode::Char).chain([KeyCode::Enter]) {
+                app.handle_key_event(KeyEvent::new(key, KeyModifiers::NONE));
+            }
+        }
+        app.handle_packet(Packet::Edit { username: "alice".to_string(), old: "hi".to_string(), message: "yo".to_string() });
+        app.handle_packet(Packet::Unsend { username: "alice".to_string(), message: "hi".to_string() });
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 80, 24));
+        (&app).render(buf.area, &mut buf);
+        let rows: Vec<String> = (0..24).map(|y| (0..80).map(|x| buf[(x, y)].symbol()).collect()).collect();
+        let said: Vec<&str> = rows.iter().filter_map(|row| row.split_once("[alice] ")).filter_map(|(_, said)| said.split(" ◷").next()).collect();
+        assert_eq!(said, ["hi", "message deleted", "yo (edited)"], "{:?}", rows);
+    }
+}
+
+#[cfg(test)]
+mod themes {
+    use ratatui::{buffer::Buffer, layout::Rect, style::{Color, Modifier, Style}};
+    use crate::theme::Theme;
+
+    fn drawn() -> Buffer {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 3, 1));
+        buf.set_string(0, 0, "a", Style::default().fg(Color::Cyan));
+        buf.set_string(1, 0, "b", Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM));
+        buf
+    }
+
+    #[test]
+    fn background_fills_only_the_gaps() {
+        let mut buf = drawn();
+        buf.set_string(2, 0, "c", Style::default().bg(Color::Red));
+        crate::theme::fill_background(buf.area, &mut buf, Color::Blue);
+        assert_eq!(buf[(0, 0)].bg, Color::Blue);
+        assert_eq!(buf[(2, 0)].bg, Color::Red);
+    }
+
+    #[test]
+    fn colors_follow_the_terminal() {
+        use crate::theme::Colors;
+        assert_eq!(Colors::detect(Some("xterm-256color"), None), Colors::Ansi256);
+        assert_eq!(Colors::detect(Some("xterm-256color"), Some("truecolor")), Colors::TrueColor);
+        assert_eq!(Colors::detect(Some("linux"), None), Colors::Ansi16);
+        assert_eq!(Colors::detect(Some("dumb"), None), Colors::Mono);
+        assert_eq!(Colors::detect(None, None), Colors::Mono);
+
+        assert_eq!(Colors::Ansi256.reduce(Color::Rgb(0x1e, 0x1e, 0x2e)), Color::Indexed(235));
+        assert_eq!(Colors::Ansi256.reduce(Color::Rgb(255, 0, 0)), Color::Indexed(196));
+        assert_eq!(Colors::Ansi16.reduce(Color::Rgb(250, 10, 10)), Color::LightRed);
+        assert_eq!(Colors::Ansi16.reduce(Color::Indexed(234)), Color::Black);
+        assert_eq!(Colors::Ansi16.reduce(Color::Cyan), Color::Cyan);
+        assert_eq!(Colors::TrueColor.reduce(Color::Rgb(1, 2, 3)), Color::Rgb(1, 2, 3));
+
+        let mut buf = drawn();
+        Colors::Mono.apply(buf.area, &mut buf);
+        assert_eq!(buf[(0, 0)].fg, Color::Reset);
+        assert!(buf[(1, 0)].modifier.contains(Modifier::DIM));
+
+        use crate::config::Config;
+        assert_eq!(Config::default().color, None);
+        assert_eq!(Config::parse("color = 256").unwrap().color, Some(Colors::Ansi256));
+        assert_eq!(Config::parse("color = false").unwrap().color, Some(Colors::Mono));
+        assert!(Config::parse("color = 88").is_err());
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for theme in [Theme::Default, Theme::HighContrast] {
+            assert_eq!(Theme::parse(theme.name()), Some(theme));
+        }
+    }
+
+    #[test]
+    fn default_leaves_everything_alone() {
+        let mut buf = drawn();
+        Theme::Default.apply(buf.area, &mut buf);
+        assert_eq!(buf, drawn());
+    }
+
+    #[test]
+    fn high_contrast_brightens_and_never_dims() {
+        let mut buf = drawn();
+        Theme::HighContrast.apply(buf.area, &mut buf);
+        assert_eq!(buf[(0, 0)].fg, Color::LightCyan);
+        assert_eq!(buf[(1, 0)].fg, Color::White);
+        assert!(!buf[(1, 0)].modifier.contains(Modifier::DIM));
+        assert!(buf[(1, 0)].modifier.contains(Modifier::ITALIC));
+        assert!(buf.content.iter().all(|cell| cell.bg == Color::Black && cell.modifier.contains(Modifier::BOLD)));
+    }
+}
+
+#[cfg(test)]
+mod stats {
+    use std::time::Duration;
+    use crate::stats::{bytes, Stats};
+
+    #[test]
+    fn byte_units() {
+        assert_eq!(bytes(0), "0 B");
+        assert_eq!(bytes(1023), "1023 B");
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(3 * 1048576), "3.0 MiB");
+    }
+
+    #[test]
+    fn summary() {
+        let mut stats = Stats::default();
+        stats.sent(10);
+        stats.sent(20);
+        stats.received(2048);
+        assert_eq!(stats.summary(1), "out 2 packets, 30 B | in 1 packets, 2.0 KiB | 1 unreadable | rtt -");
+        stats.rtt = Some(Duration::from_millis(42));
+        assert!(stats.summary(0).ends_with("rtt 42 ms"));
+    }
+}
+
+#[cfg(test)]
+mod roster {
+    use crate::roster::Roster;
+
+    fn names(roster: &Roster) -> Vec<&str> {
+        roster.iter().map(|user| user.name.as_str()).collect()
+    }
+
+    #[test]
+    fn joins_are_not_repeated() {
+        let mut roster = Roster::default();
+        assert!(roster.join("alice".to_string()));
+        assert!(roster.join("bob".to_string()));
+        assert!(!roster.join("alice".to_string()));
+        assert_eq!(names(&roster), vec!["alice", "bob"]);
+    }
+
+    #[test]
+    fn replayed_join_packet_shows_once() {
+        use std::time::{Duration, Instant};
+        use crate::{app::record_join, presence::{Change, Presence}, protocol::Packet};
+        let now = Instant::now();
+        let (mut roster, mut presence) = (Roster::default(), Presence::default());
+        let packet = Packet::Join("alice".to_string()).encode();
+        for _ in 0..2 {
+            if let Some(Packet::Join(username)) = Packet::decode(&packet) {
+                record_join(&mut roster, &mut presence, username, now);
+            }
+        }
+        assert_eq!(names(&roster), vec!["alice"]);
+        assert_eq!(presence.flush(now + Duration::from_secs(1)), vec![(Change::Joined, vec!["alice".to_string()])]);
+    }
+
+    #[test]
+    fn leaves_prune_the_user() {
+        let mut roster = Roster::default();
+        roster.join("alice".to_string());
+        roster.set_away("alice", true);
+        assert!(roster.leave("alice"));
+        assert!(!roster.leave("alice"));
+        assert_eq!(roster.iter().count(), 0);
+        // coming back starts over
+        roster.join("alice".to_string());
+        assert!(!roster.iter().next().unwrap().away);
+    }
+
+    #[test]
+    fn rename_keeps_place_and_state() {
+        let mut roster = Roster::default();
+        roster.join("alice".to_string());
+        roster.join("bob".to_string());
+        roster.set_away("alice", true);
+        roster.rename("alice", "carol".to_string());
+        assert_eq!(names(&roster), vec!["carol", "bob"]);
+        assert!(roster.iter().next().unwrap().away);
+        assert!(!roster.contains("alice"));
+    }
+
+    #[test]
+    fn title_counts_the_room() {
+        use crate::app::history_title;
+        let mut roster = Roster::default();
+        assert_eq!(history_title("", roster.len()), " Blossom ");
+        roster.join("alice".to_string());
+        roster.join("bob".to_string());
+        roster.join("alice".to_string());
+        assert_eq!(history_title("", roster.len()), " Blossom (2) ");
+        roster.leave("bob");
+        assert_eq!(history_title("standup", roster.len()), " standup (1) ");
+    }
+}
+
+#[cfg(test)]
+mod status_line {
+    use std::time::Duration;
+    use crate::{config::Config, status::{self, line, Info, Segment}};
+
+    #[test]
+    fn segments_in_the_given_order() {
+        assert_eq!(status::parse("unread, users"), Some(vec![Segment::Unread, Segment::Users]));
+        assert_eq!(status::parse(""), Some(vec![]));
+        assert_eq!(status::parse("users, weather"), None);
+        assert!(Config::default().status.is_empty());
+        assert!(Config::parse("status = clock").is_err());
+    }
+
+    #[test]
+    fn status_line() {
+        let info = Info { connected: true, users: 1, server: "[200::1]:9595".to_string(), port: Some(40123), rtt: Some(Duration::from_millis(80)), unread: 3, time: "12:00".to_string() };
+        let segments = status::parse("time, connection, users, rtt, unread").unwrap();
+        assert_eq!(line(&segments, &info), "12:00 | connected | 1 user | rtt 80 ms | 3 unread");
+        assert_eq!(line(&[Segment::Server, Segment::Port], &info), "[200::1]:9595 | port 40123");
+        assert_eq!(line(&[], &info), "");
+    }
+}
+
+#[cfg(test)]
+mod roomkeys {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use crate::{app::decode_roomkey, crypt::convert_to_32_bytes};
+
+    fn roomkey(addr: &str) -> String {
+        BASE64_STANDARD.encode(convert_to_32_bytes(addr.to_string()))
+    }
+
+    #[test]
+    fn roomkey_roundtrip() {
+        let key = roomkey("[200:1234::1]:9595");
+        assert_eq!(decode_roomkey(&key).unwrap(), (key.clone(), "[200:1234::1]:9595".to_string()));
+    }
+
+    #[test]
+    fn pasted_roomkey_with_whitespace() {
+        let key = roomkey("[200:1234::1]:9595");
+        let pasted = format!("  {}\n", key);
+        assert_eq!(decode_roomkey(&pasted).unwrap().0, key);
+        let broken = format!("{}\r\n{}", &key[..10], &key[10..]);
+        assert_eq!(decode_roomkey(&broken).unwrap().1, "[200:1234::1]:9595");
+    }
+
+    #[test]
+    fn malformed_roomkeys() {
+        assert!(decode_roomkey("").is_err());
+        assert!(decode_roomkey(" \n").is_err());
+        assert!(decode_roomkey("not base64!").is_err());
+        // valid base64, but not text
+        assert!(decode_roomkey(&BASE64_STANDARD.encode([0xff, 0xfe])).is_err());
+        assert!(decode_roomkey(&BASE64_STANDARD.encode("gggg")).is_err());
+    }
+}
+
+#[cfg(test)]
+mod typing {
+    use std::time::{Duration, Instant};
+    use crate::{protocol::Packet, typing::{indicator, Typing}};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn typing_roundtrip() {
+        for typing in [true, false] {
+            let packet = Packet::Typing { username: "alice".to_string(), typing };
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn typists_in_the_order_they_started() {
+        let now = Instant::now();
+        let mut typing = Typing::default();
+        typing.set("alice", true, now);
+        typing.set("bob", true, now);
+        typing.set("alice", true, now + Duration::from_secs(1));
+        assert_eq!(typing.names(now + Duration::from_secs(1)), names(&["alice", "bob"]));
+        typing.set("alice", false, now + Duration::from_secs(2));
+        assert_eq!(typing.names(now + Duration::from_secs(2)), names(&["bob"]));
+    }
+
+    #[test]
+    fn lost_stop_packets_expire() {
+        let now = Instant::now();
+        let mut typing = Typing::default();
+        typing.set("alice", true, now);
+        typing.set("bob", true, now + Duration::from_secs(3));
+        assert_eq!(typing.names(now + Duration::from_secs(6)), names(&["bob"]));
+        assert!(typing.names(now + Duration::from_secs(9)).is_empty());
+    }
+
+    #[test]
+    fn indicator_text() {
+        assert_eq!(indicator(&names(&["Alice"]), 0), "Alice is typing.");
+        assert_eq!(indicator(&names(&["Alice", "Bob"]), 1), "Alice and Bob are typing..");
+        assert_eq!(indicator(&names(&["Alice", "Bob", "Carol", "Dave"]), 5), "Alice, Bob and 2 others are typing...");
+    }
+}
+
+#[cfg(test)]
+mod throttle {
+    use std::time::{Duration, Instant};
+    use crate::{config::Config, protocol::Packet, throttle::Throttle};
+
+    fn typing(typing: bool) -> Packet {
+        Packet::Typing { username: "alice".to_string(), typing }
+    }
+
+    #[test]
+    fn only_the_newest_of_a_kind_waits() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(Duration::from_secs(1));
+        assert_eq!(throttle.push(typing(true), now), Some(typing(true)));
+        // other kinds go out on their own schedule
+        let away = Packet::Status { username: "alice".to_string(), away: true };
+        assert_eq!(throttle.push(away, now), Some(Packet::Status { username: "alice".to_string(), away: true }));
+        assert_eq!(throttle.push(typing(false), now), None);
+        assert_eq!(throttle.push(typing(true), now + Duration::from_millis(100)), None);
+        assert!(throttle.due(now + Duration::from_millis(900)).is_empty());
+        // jitter stretches the wait by a quarter at most
+        assert_eq!(throttle.due(now + Duration::from_millis(1250)), vec![typing(true)]);
+        assert!(throttle.due(now + Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn held_packets_go_out_on_exit() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(Duration::from_secs(1));
+        throttle.push(typing(true), now);
+        throttle.push(typing(false), now);
+        assert_eq!(throttle.drain(), vec![typing(false)]);
+        assert!(throttle.drain().is_empty());
+    }
+
+    #[test]
+    fn cadences_are_configurable() {
+        let config = Config::parse("typing_refresh = 2\ncontrol_interval = 250").unwrap();
+        assert_eq!(config.typing_refresh, Duration::from_secs(2));
+        assert_eq!(config.control_interval, Duration::from_millis(250));
+        assert!(Config::parse("typing_refresh = 0").is_err());
+        assert!(Config::parse("typing_refresh = 5").is_err());
+        assert!(Config::parse("control_interval = fast").is_err());
+    }
+}
+
+#[cfg(test)]
+mod wizard {
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use crate::{config::Config, transport::Kind, wizard::{Answers, Step, Wizard}};
+
+    fn press(wizard: &mut Wizard, code: KeyCode) -> Step {
+        wizard.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn type_in(wizard: &mut Wizard, text: &str) {
+        for c in text.chars() {
+            assert_eq!(press(wizard, KeyCode::Char(c)), Step::Continue);
+        }
+    }
+
+    #[test]
+    fn fills_in_the_form() {
+        let mut wizard = Wizard::default();
+        type_in(&mut wizard, "alice");
+        press(&mut wizard, KeyCode::Enter);
+        press(&mut wizard, KeyCode::Enter);
+        press(&mut wizard, KeyCode::Right);
+        assert_eq!(press(&mut wizard, KeyCode::Enter), Step::Done(Answers { username: "alice".to_string(), roomkey: String::new(), transport: Kind::Tcp }));
+    }
+
+    #[test]
+    fn bad_room_keys_are_sent_back() {
+        let mut wizard = Wizard::default();
+        press(&mut wizard, KeyCode::Tab);
+        type_in(&mut wizard, "not a key!");
+        press(&mut wizard, KeyCode::Tab);
+        assert_eq!(press(&mut wizard, KeyCode::Enter), Step::Continue);
+        assert_eq!(press(&mut wizard, KeyCode::Esc), Step::Cancel);
+    }
+
+    #[test]
+    fn username_from_the_config() {
+        assert_eq!(Config::parse("username = alice").unwrap().username, "alice");
+        assert!(Config::parse("username = a|b").is_err());
+    }
+}
+
+#[cfg(test)]
+mod args {
+    use crate::{args::parse, config::Config, transport::Kind};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn trailing_flag_is_missing_its_value() {
+        for flag in ["--username", "-r", "--port", "--transport", "--log-file"] {
+            let e = parse(args(&["-u", "alice", flag]), &mut Config::default()).unwrap_err();
+            assert_eq!(e.to_string(), format!("missing value for {}", flag));
+        }
+    }
+
+    #[test]
+    fn values_are_checked() {
+        let mut config = Config::default();
+        let parsed = parse(args(&["-u", "alice", "-t", "tcp", "--servers", "a, b,"]), &mut config).unwrap();
+        assert_eq!(parsed.username, "alice");
+        assert_eq!(parsed.servers, ["a", "b"]);
+        assert_eq!(parsed.port, None);
+        assert_eq!(parse(args(&["-p", "9000"]), &mut config).unwrap().port.as_deref(), Some("9000"));
+        assert_eq!(config.transport, Kind::Tcp);
+        assert_eq!(parse(args(&["--transport", "quic"]), &mut config).unwrap_err().to_string(), "--transport takes udp or tcp");
+        assert!(parse(args(&["--bind", "localhost"]), &mut config).is_err());
+        assert!(parse(args(&["--what"]), &mut config).unwrap().help);
+        assert!(!config.quiet);
+        parse(args(&["--quiet"]), &mut config).unwrap();
+        assert!(config.quiet);
+        assert!(parse(args(&["--offline"]), &mut config).unwrap().offline);
+        assert!(parse(args(&["-V"]), &mut config).unwrap().version);
+        assert!(crate::args::version().ends_with(&format!("(protocol {})", crate::protocol::VERSION)));
+        config.show_users = false;
+        parse(args(&["--show-users"]), &mut config).unwrap();
+        assert!(config.show_users);
+        assert!(!Config::parse("show_key = false").unwrap().show_key);
+    }
+
+    #[test]
+    fn usernames_have_a_limit() {
+        let long = "a".repeat(65);
+        assert_eq!(parse(args(&["-u", &long]), &mut Config::default()).unwrap_err().to_string(), "-u takes a name of at most 64 bytes, without \"|\"");
+        assert!(parse(args(&["-u", "a|b"]), &mut Config::default()).is_err());
+        assert_eq!(parse(args(&["-u", &long[1..]]), &mut Config::default()).unwrap().username.len(), 64);
+        assert!(Config::parse(&format!("username = {}", long)).is_err());
+    }
+
+    #[test]
+    fn roomkey_file_keeps_the_first_line() {
+        let path = std::env::temp_dir().join(format!("blossom-roomkey-{}", std::process::id()));
+        std::fs::write(&path, "  abcdefg \nsomething else\n").unwrap();
+        let flag = path.to_string_lossy().to_string();
+        let parsed = parse(args(&["--roomkey-file", &flag]), &mut Config::default());
+        std::fs::write(&path, "\n").unwrap();
+        let empty = parse(args(&["--roomkey-file", &flag]), &mut Config::default());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(parsed.unwrap().roomkey, "abcdefg");
+        assert_eq!(empty.unwrap_err().to_string(), format!("--roomkey-file {} is empty", flag));
+        assert!(parse(args(&["--roomkey-file", "/nonexistent/blossom"]), &mut Config::default()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod loopback {
+    use std::{net::{IpAddr, Ipv4Addr, SocketAddr}, thread, time::{Duration, Instant}};
+    use crate::{protocol::{LeaveReason, Packet, MAX_USERNAME}, server::{self, Command}, transport::{self, Kind, Transport}};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn join(kind: Kind, server: SocketAddr, name: &str) -> Box<dyn Transport> {
+        let mut client = transport::bind(kind, LOCALHOST, "0").unwrap();
+        client.connect(&server.to_string()).unwrap();
+        client.send(&Packet::Join(name.to_string()).encode()).unwrap();
+        client
+    }
+
+    /// Waits for the first packet `wanted` accepts, skipping the others.
+    fn expect(client: &mut Box<dyn Transport>, wanted: impl Fn(&Packet) -> bool) -> Packet {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match client.try_recv().unwrap().map(|bytes| Packet::decode(&bytes)) {
+                Some(Some(packet)) if wanted(&packet) => return packet,
+                Some(_) => {}
+                None => thread::sleep(Duration::from_millis(5))
+            }
+        }
+        panic!("the packet never arrived");
+    }
+
+    #[test]
+    fn two_clients_talk_through_the_server() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), "lunch".to_string(), None).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        expect(&mut alice, |packet| *packet == Packet::Join("alice".to_string()));
+        // one over each transport, the server relays between them
+        let mut bob = join(Kind::Tcp, addr, "bob");
+        expect(&mut bob, |packet| *packet == Packet::Topic("lunch".to_string()));
+        expect(&mut alice, |packet| *packet == Packet::Join("bob".to_string()));
+
+        let hello = Packet::Chat { username: "alice".to_string(), message: "anyone | hungry?".to_string(), sent: 0 };
+        alice.send(&hello.encode()).unwrap();
+        assert_eq!(expect(&mut bob, |packet| matches!(packet, Packet::Chat { .. })), hello);
+        // the sender gets it back too, that's how it knows it was delivered
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Chat { .. })), hello);
+
+        // bob can't speak for alice
+        bob.send(&Packet::Chat { username: "alice".to_string(), message: "I quit".to_string(), sent: 0 }.encode()).unwrap();
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Chat { .. })), Packet::Chat { username: "bob".to_string(), message: "I quit".to_string(), sent: 0 });
+        // nor claim the host kicked him
+        bob.send(&Packet::Leave { username: "alice".to_string(), reason: LeaveReason::Kicked }.encode()).unwrap();
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Leave { .. })), Packet::Leave { username: "bob".to_string(), reason: LeaveReason::Quit });
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn private_messages_only_reach_their_recipients() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        let mut bob = join(Kind::Udp, addr, "bob");
+        let mut carol = join(Kind::Udp, addr, "carol");
+        expect(&mut alice, |packet| *packet == Packet::Join("carol".to_string()));
+        expect(&mut carol, |packet| *packet == Packet::Join("carol".to_string()));
+
+        // the server puts the real sender in
+        alice.send(&Packet::Private { username: "carol".to_string(), to: vec!["bob".to_string()], message: "psst".to_string() }.encode()).unwrap();
+        let sent = Packet::Private { username: "alice".to_string(), to: vec!["bob".to_string()], message: "psst".to_string() };
+        assert_eq!(expect(&mut bob, |packet| matches!(packet, Packet::Private { .. })), sent);
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Private { .. })), sent);
+        // a chat sent after it reaches carol, the private message never did
+        alice.send(&Packet::Chat { username: "alice".to_string(), message: "hi all".to_string(), sent: 0 }.encode()).unwrap();
+        assert!(matches!(expect(&mut carol, |packet| matches!(packet, Packet::Private { .. } | Packet::Chat { .. })), Packet::Chat { .. }));
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn nobody_unsends_in_someone_elses_name() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        let mut bob = join(Kind::Udp, addr, "bob");
+        expect(&mut alice, |packet| *packet == Packet::Join("bob".to_string()));
+        expect(&mut bob, |packet| *packet == Packet::Join("bob".to_string()));
+
+        alice.send(&Packet::Chat { username: "alice".to_string(), message: "lunch?".to_string(), sent: 0 }.encode()).unwrap();
+        expect(&mut bob, |packet| matches!(packet, Packet::Chat { .. }));
+        // a forged message and an unsend both end up as bob's, neither touches alice's
+        bob.send(&Packet::Chat { username: "alice".to_string(), message: "no lunch".to_string(), sent: 0 }.encode()).unwrap();
+        bob.send(&Packet::Unsend { username: "alice".to_string(), message: "lunch?".to_string() }.encode()).unwrap();
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Chat { username, .. } if username != "alice")), Packet::Chat { username: "bob".to_string(), message: "no lunch".to_string(), sent: 0 });
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Unsend { .. })), Packet::Unsend { username: "bob".to_string(), message: "lunch?".to_string() });
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn a_tcp_user_that_stops_reading_doesnt_hold_up_the_room() {
+        use std::net::TcpStream;
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        transport::write_frame(&mut stalled, &Packet::Join("stalled".to_string()).encode()).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        expect(&mut alice, |packet| *packet == Packet::Join("alice".to_string()));
+
+        // far more than fits in the socket buffers and the backlog together
+        let chat = Packet::Chat { username: "alice".to_string(), message: "x".repeat(60_000), sent: 0 }.encode();
+        let deadline = Instant::now() + Duration::from_secs(20);
+        let mut left = false;
+        while !left && Instant::now() < deadline {
+            alice.send(&chat).unwrap();
+            thread::sleep(Duration::from_millis(1));
+            while let Some(bytes) = alice.try_recv().unwrap() {
+                left |= Packet::decode(&bytes) == Some(Packet::Leave { username: "stalled".to_string(), reason: LeaveReason::Timeout });
+            }
+        }
+        assert!(left, "the stalled user was never hung up on");
+        // and the room still answers
+        alice.send(&Packet::Chat { username: "alice".to_string(), message: "still here".to_string(), sent: 0 }.encode()).unwrap();
+        expect(&mut alice, |packet| matches!(packet, Packet::Chat { message, .. } if message == "still here"));
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn only_packets_the_server_understands_are_relayed() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        let bob = join(Kind::Udp, addr, "bob");
+        expect(&mut alice, |packet| *packet == Packet::Join("bob".to_string()));
+
+        bob.send(&Packet::Join("admin".to_string()).encode()).unwrap();
+        bob.send(b"\xffnot a packet").unwrap();
+        bob.send(&Packet::Chat { username: "bob".to_string(), message: "hi".to_string(), sent: 0 }.encode()).unwrap();
+        // everything before bob's chat, which comes after the other two
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut before = Vec::new();
+        while Instant::now() < deadline {
+            match alice.try_recv().unwrap() {
+                Some(bytes) if matches!(Packet::decode(&bytes), Some(Packet::Chat { .. })) => break,
+                Some(bytes) => before.push(bytes),
+                None => thread::sleep(Duration::from_millis(5))
+            }
+        }
+        assert!(Instant::now() < deadline, "the chat never arrived");
+        assert!(before.iter().all(|bytes| !matches!(Packet::decode(bytes), None | Some(Packet::Join(_)))), "{:?}", before);
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn oversized_names_are_not_let_in() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let _mallory = join(Kind::Udp, addr, &"m".repeat(MAX_USERNAME + 1));
+        thread::sleep(Duration::from_millis(50));
+        // everyone already in the room is announced to a newcomer before their own join
+        let mut alice = join(Kind::Udp, addr, "alice");
+        assert_eq!(expect(&mut alice, |packet| matches!(packet, Packet::Join(_))), Packet::Join("alice".to_string()));
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn refused_renames_are_sent_back() {
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), None).unwrap();
+        let _alice = join(Kind::Udp, addr, "alice");
+        let mut bob = join(Kind::Udp, addr, "bob");
+        expect(&mut bob, |packet| *packet == Packet::Join("bob".to_string()));
+        bob.send(&Packet::Nick { old: "bob".to_string(), new: "alice".to_string() }.encode()).unwrap();
+        assert_eq!(expect(&mut bob, |packet| matches!(packet, Packet::Nick { .. })), Packet::Nick { old: "alice".to_string(), new: "bob".to_string() });
+        let long = "b".repeat(MAX_USERNAME + 1);
+        bob.send(&Packet::Nick { old: "bob".to_string(), new: long.clone() }.encode()).unwrap();
+        assert_eq!(expect(&mut bob, |packet| matches!(packet, Packet::Nick { .. })), Packet::Nick { old: long, new: "bob".to_string() });
+        server.send(Command::Shutdown).unwrap();
+    }
+
+    #[test]
+    fn silent_users_time_out() {
+        let timeout = Duration::from_millis(400);
+        let (addr, server) = server::listen(SocketAddr::new(LOCALHOST, 0), String::new(), Some(timeout)).unwrap();
+        let mut alice = join(Kind::Udp, addr, "alice");
+        let mut bob = join(Kind::Udp, addr, "bob");
+        expect(&mut bob, |packet| *packet == Packet::Join("bob".to_string()));
+        // alice keeps pinging, bob crashed
+        let start = Instant::now();
+        let mut left = None;
+        while left.is_none() && start.elapsed() < Duration::from_secs(5) {
+            alice.send(&Packet::Ping.encode()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            while let Some(bytes) = alice.try_recv().unwrap() {
+                if let Some(packet @ Packet::Leave { .. }) = Packet::decode(&bytes) {
+                    left = Some((packet, start.elapsed()));
+                }
+            }
+        }
+        let (packet, after) = left.expect("bob never timed out");
+        assert_eq!(packet, Packet::Leave { username: "bob".to_string(), reason: LeaveReason::Timeout });
+        assert!(after >= timeout - Duration::from_millis(100));
+        // and he's told, so he can join again if he's still there
+        assert_eq!(expect(&mut bob, |packet| matches!(packet, Packet::Leave { .. })), packet);
+        server.send(Command::Shutdown).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod fuzz {
+    use std::io::Cursor;
+    use aes_gcm::{Aes256Gcm, Key, KeyInit};
+    use proptest::{collection::vec, prelude::*};
+    use crate::{app::decode_roomkey, crypt::decrypt, protocol::Packet, transport::read_frame};
+
+    proptest! {
+        // anyone can send anything to the open port
+        #[test]
+        fn any_bytes_decode_without_panicking(bytes in vec(any::<u8>(), 0..2048)) {
+            let _ = Packet::decode(&bytes);
+        }
+
+        // random bytes rarely start with a known tag, so give every tag its share
+        #[test]
+        fn tagged_garbage_decodes_without_panicking(tag in 0u8..=0x0C, payload in vec(any::<u8>(), 0..64)) {
+            let mut bytes = vec![tag];
+            bytes.extend(payload);
+            if let Some(packet) = Packet::decode(&bytes) {
+                prop_assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+            }
+        }
+
+        #[test]
+        fn any_stream_reads_without_panicking(bytes in vec(any::<u8>(), 0..64)) {
+            let mut stream = Cursor::new(bytes);
+            while read_frame(&mut stream).is_ok() {}
+        }
+
+        #[test]
+        fn any_ciphertext_decrypts_without_panicking(bytes in vec(any::<u8>(), 0..64)) {
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[0u8; 32]));
+            prop_assert!(decrypt(&cipher, &bytes).is_err());
+        }
+
+        #[test]
+        fn any_roomkey_decodes_without_panicking(roomkey in ".{0,40}") {
+            let _ = decode_roomkey(&roomkey);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sent {
+    use crate::sent::{worth_saving, Sent};
+
+    #[test]
+    fn goes_back_and_forth_like_a_shell() {
+        let mut sent = Sent::default();
+        assert_eq!(sent.older(), None);
+        for line in ["one", "two", "two", "three"] {
+            sent.push(line);
+        }
+        assert_eq!(sent.older(), Some("three"));
+        assert_eq!(sent.older(), Some("two"));
+        assert_eq!(sent.older(), Some("one"));
+        assert_eq!(sent.older(), Some("one"));
+        assert_eq!(sent.newer(), Some("two"));
+        assert_eq!(sent.newer(), Some("three"));
+        assert_eq!(sent.newer(), None);
+        assert_eq!(sent.newer(), None);
+    }
+
+    #[test]
+    fn saves_messages_but_not_commands_or_secrets() {
+        assert!(worth_saving("see you at 10"));
+        assert!(!worth_saving("/nick bob"));
+        assert!(!worth_saving("the password is hunter2hunter2hunter2"));
+
+        let path = std::env::temp_dir().join(format!("blossom-sent-{}", std::process::id()));
+        let mut sent = Sent::default();
+        for line in ["first\nsecond", "back\\slash", "/away", "WzIwMDo6MV06OTU5NQ=="] {
+            sent.push(line);
+        }
+        sent.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let mut loaded = Sent::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.older(), Some("back\\slash"));
+        assert_eq!(loaded.older(), Some("first\nsecond"));
+        assert_eq!(loaded.older(), Some("first\nsecond"));
+        assert!(Sent::load(&path).unwrap().older().is_none());
+    }
+}
+
+#[cfg(test)]
+mod groups {
+    use crate::groups::{expand, format, parse};
+
+    #[test]
+    fn groups_expand_to_their_members() {
+        let groups = parse("team: alice bob, ops: carol bob").unwrap();
+        assert_eq!(parse(&format(&groups)), Some(groups.clone()));
+        assert_eq!(expand("@team,dave,@ops", &groups), Ok(vec!["alice".to_string(), "bob".to_string(), "dave".to_string(), "carol".to_string()]));
+        assert_eq!(expand("@nobody", &groups), Err("nobody".to_string()));
+        assert_eq!(parse("team alice"), None);
+        assert_eq!(parse("team:"), None);
+    }
+}
+
+#[cfg(test)]
+mod private {
+    use ratatui::{buffer::Buffer, layout::Rect, widgets::Widget};
+    use crate::{app::App, config::Config, protocol::Packet};
+
+    fn rows(app: &App) -> Vec<String> {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 80, 24));
+        app.render(buf.area, &mut buf);
+        (0..24).map(|y| (0..80).map(|x| buf[(x, y)].symbol()).collect()).collect()
+    }
+
+    #[test]
+    fn messages_for_others_are_left_out() {
+        let mut app = App::offline("alice".to_string(), Config::default());
+        let before = rows(&app);
+        app.handle_packet(Packet::Private { username: "bob".to_string(), to: vec!["carol".to_string()], message: "not for alice".to_string() });
+        assert_eq!(rows(&app), before);
+        app.handle_packet(Packet::Private { username: "bob".to_string(), to: vec!["carol".to_string(), "alice".to_string()], message: "for both".to_string() });
+        assert!(rows(&app).iter().any(|row| row.contains("for both")));
+    }
+}
+
+#[cfg(test)]
+mod prefix {
+    use crate::prefix::{parse, Piece, DEFAULT};
+
+    #[test]
+    fn templates_end_in_the_message() {
+        assert_eq!(parse(DEFAULT), Some(vec![Piece::Text("[".to_string()), Piece::User, Piece::Text("] ".to_string())]));
+        assert_eq!(parse("{time} <{user}> {msg}"), Some(vec![Piece::Time, Piece::Text(" <".to_string()), Piece::User, Piece::Text("> ".to_string())]));
+        assert_eq!(parse("{msg}"), Some(Vec::new()));
+        for broken in ["{user}: {msg} ({time})", "{user} {user} {msg}", "{nick}: {msg}", "{user: {msg}", "{user}"] {
+            assert_eq!(parse(broken), None, "{}", broken);
+        }
+    }
+}
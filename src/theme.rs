@@ -0,0 +1,197 @@
+use ratatui::{buffer::Buffer, layout::Rect, style::{Color, Modifier, Stylize}, text::Line};
+
+/// How the UI is colored.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Theme {
+    Default,
+    /// Bold bright text on black for low vision. Nothing is dimmed: what
+    /// would be dim (away users, timestamps) is italic instead.
+    HighContrast,
+}
+
+impl Theme {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "default" => Some(Theme::Default),
+            "high-contrast" => Some(Theme::HighContrast),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    /// Recolors what has been drawn into `area`. Like turning colors off, this
+    /// happens after drawing so every place that sets a color doesn't have to
+    /// know about themes.
+    pub fn apply(&self, area: Rect, buf: &mut Buffer) {
+        if *self == Theme::Default {
+            return;
+        }
+        for position in area.positions() {
+            if let Some(cell) = buf.cell_mut(position) {
+                let mut modifier = cell.modifier | Modifier::BOLD;
+                if modifier.contains(Modifier::DIM) {
+                    modifier = (modifier - Modifier::DIM) | Modifier::ITALIC;
+                }
+                cell.modifier = modifier;
+                cell.set_fg(high_contrast(cell.fg)).set_bg(Color::Black);
+            }
+        }
+    }
+}
+
+/// How many colors the terminal can show. Whatever is drawn in more than
+/// that is brought down to the closest one it has, instead of sending codes
+/// the terminal can't read.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Colors {
+    /// No colors at all. Bold and italic stay.
+    Mono,
+    /// The 16 named colors.
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl Colors {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mono" => Some(Colors::Mono),
+            "16" => Some(Colors::Ansi16),
+            "256" => Some(Colors::Ansi256),
+            "truecolor" => Some(Colors::TrueColor),
+            _ => None
+        }
+    }
+
+    /// Guesses from `$TERM` and `$COLORTERM`, the way most terminal programs
+    /// do. Terminals that can show more than their `$TERM` says just get
+    /// fewer colors than they could.
+    pub fn detect(term: Option<&str>, colorterm: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            return Colors::TrueColor;
+        }
+        match term {
+            None | Some("" | "dumb") => Colors::Mono,
+            Some(term) if term.ends_with("-direct") => Colors::TrueColor,
+            Some(term) if term.contains("256color") => Colors::Ansi256,
+            Some(_) => Colors::Ansi16
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok();
+        Colors::detect(var("TERM").as_deref(), var("COLORTERM").as_deref())
+    }
+
+    /// Brings everything drawn into `area` down to these colors. Like the
+    /// themes, this happens after drawing, colors come from all over the place.
+    pub fn apply(&self, area: Rect, buf: &mut Buffer) {
+        if *self == Colors::TrueColor {
+            return;
+        }
+        for position in area.positions() {
+            if let Some(cell) = buf.cell_mut(position) {
+                let (fg, bg) = (self.reduce(cell.fg), self.reduce(cell.bg));
+                cell.set_fg(fg).set_bg(bg);
+            }
+        }
+    }
+
+    pub fn reduce(&self, color: Color) -> Color {
+        match (self, color) {
+            (Colors::TrueColor, color) | (_, color @ Color::Reset) => color,
+            (Colors::Mono, _) => Color::Reset,
+            (Colors::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(to_256(r, g, b)),
+            (Colors::Ansi16, Color::Rgb(r, g, b)) => to_16(r, g, b),
+            (Colors::Ansi16, Color::Indexed(index)) => {
+                let (r, g, b) = rgb_of(index);
+                to_16(r, g, b)
+            }
+            (_, color) => color
+        }
+    }
+}
+
+// the named colors as xterm shows them, in the order of their indexes
+const NAMED: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)), (Color::Red, (205, 0, 0)), (Color::Green, (0, 205, 0)), (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)), (Color::Magenta, (205, 0, 205)), (Color::Cyan, (0, 205, 205)), (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)), (Color::LightRed, (255, 0, 0)), (Color::LightGreen, (0, 255, 0)), (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)), (Color::LightMagenta, (255, 0, 255)), (Color::LightCyan, (0, 255, 255)), (Color::White, (255, 255, 255)),
+];
+
+// the steps of each channel in the 6x6x6 cube that indexes 16 to 231 are
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+fn rgb_of(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => NAMED[index as usize].1,
+        16..=231 => {
+            let i = index - 16;
+            (CUBE[(i / 36) as usize], CUBE[(i / 6 % 6) as usize], CUBE[(i % 6) as usize])
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+fn to_256(r: u8, g: u8, b: u8) -> u8 {
+    let step = |c: u8| (0..6).min_by_key(|&i| (CUBE[i] as i32 - c as i32).abs()).unwrap_or(0) as u8;
+    let cube = 16 + 36 * step(r) + 6 * step(g) + step(b);
+    let gray = 232 + ((r as u32 + g as u32 + b as u32) / 3).saturating_sub(3).min(238) as u8 / 10;
+    [cube, gray].into_iter().min_by_key(|&index| distance(rgb_of(index), (r, g, b))).unwrap_or(cube)
+}
+
+fn to_16(r: u8, g: u8, b: u8) -> Color {
+    NAMED.iter().min_by_key(|(_, rgb)| distance(*rgb, (r, g, b))).map_or(Color::Reset, |(color, _)| *color)
+}
+
+/// Paints `color` behind everything in `area` that was drawn without a
+/// background of its own.
+pub fn fill_background(area: Rect, buf: &mut Buffer, color: Color) {
+    for position in area.positions() {
+        if let Some(cell) = buf.cell_mut(position) {
+            if cell.bg == Color::Reset {
+                cell.set_bg(color);
+            }
+        }
+    }
+}
+
+/// A few lines styled like the history, to see a theme on before picking it.
+pub fn sample() -> Vec<Line<'static>> {
+    vec![
+        Line::from(vec!["bob".red(), " joined the room".red()]),
+        Line::from(vec!["[".cyan(), "alice".cyan(), "] ".cyan(), "is the ".gray(), "coffee".yellow().bold(), " ready?".gray()]),
+        Line::from(vec!["[".green().bold(), "you".green().bold(), "] ".green().bold(), "almost".gray(), " ✓".green()]),
+        Line::from(vec!["  ┆ ".dark_gray(), "alice: is the coffee ready?".dark_gray().italic()]),
+        Line::from("A notice from Blossom").dark_gray().italic(),
+        Line::from(vec!["12:00 ".dark_gray(), "carol".magenta(), " left the room (timed out)".magenta().italic()]),
+    ]
+}
+
+fn high_contrast(color: Color) -> Color {
+    match color {
+        Color::Red => Color::LightRed,
+        Color::Green => Color::LightGreen,
+        Color::Yellow => Color::LightYellow,
+        Color::Blue => Color::LightBlue,
+        Color::Magenta => Color::LightMagenta,
+        Color::Cyan => Color::LightCyan,
+        Color::Reset | Color::Black | Color::Gray | Color::DarkGray => Color::White,
+        color => color
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+use rand::Rng;
+
+use crate::protocol::Packet;
+
+/// Holds back control packets, like typing and away, that would go out more
+/// often than once per interval. Only the newest state of each kind matters,
+/// so a held back packet is replaced by a newer one of its kind instead of
+/// waiting in line behind it.
+///
+/// Each wait is stretched by up to a quarter at random, so clients that
+/// started out together drift apart instead of sending in bursts.
+pub struct Throttle {
+    interval: Duration,
+    slots: Vec<Slot>,
+}
+
+struct Slot {
+    tag: u8,
+    // when the next packet of this kind may go out
+    next: Instant,
+    waiting: Option<Packet>,
+}
+
+impl Throttle {
+
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, slots: Vec::new() }
+    }
+
+    /// Returns the packet if it can go out right away, otherwise holds it
+    /// until `due` hands it back.
+    pub fn push(&mut self, packet: Packet, now: Instant) -> Option<Packet> {
+        let tag = packet.encode()[0];
+        let next = now + self.wait();
+        match self.slots.iter_mut().find(|slot| slot.tag == tag) {
+            Some(slot) if now < slot.next => {
+                slot.waiting = Some(packet);
+                None
+            }
+            Some(slot) => {
+                slot.next = next;
+                slot.waiting = None;
+                Some(packet)
+            }
+            None => {
+                self.slots.push(Slot { tag, next, waiting: None });
+                Some(packet)
+            }
+        }
+    }
+
+    /// The held back packets whose wait is over.
+    pub fn due(&mut self, now: Instant) -> Vec<Packet> {
+        let wait = self.wait();
+        self.slots.iter_mut().filter(|slot| slot.waiting.is_some() && now >= slot.next).filter_map(|slot| {
+            slot.next = now + wait;
+            slot.waiting.take()
+        }).collect()
+    }
+
+    /// Every held back packet, for sending before we go.
+    pub fn drain(&mut self) -> Vec<Packet> {
+        self.slots.iter_mut().filter_map(|slot| slot.waiting.take()).collect()
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval.mul_f64(1.0 + rand::thread_rng().gen_range(0.0..0.25))
+    }
+}
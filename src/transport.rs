@@ -0,0 +1,264 @@
+use std::{cell::RefCell, collections::VecDeque, fs, io::{Error, ErrorKind, Read, Result, Write}, net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket}, sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+use crate::protocol::{self, Packet, MAX_DATAGRAM};
+
+/// How packets get to the server. UDP is the default, TCP is there for
+/// networks that block UDP.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Udp,
+    Tcp,
+}
+
+impl Kind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "udp" => Some(Kind::Udp),
+            "tcp" => Some(Kind::Tcp),
+            _ => None
+        }
+    }
+}
+
+pub trait Transport {
+    fn connect(&mut self, addr: &str) -> Result<()>;
+
+    fn send(&self, packet: &[u8]) -> Result<()>;
+
+    /// Returns the next packet if one has arrived, without blocking.
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// The address packets are sent from.
+    fn local_addr(&self) -> Result<SocketAddr>;
+}
+
+/// Binds the transport that `connect` is later called on. The address and
+/// port only matter for UDP, TCP connections get them from the system.
+pub fn bind(kind: Kind, ip: IpAddr, port: &str) -> Result<Box<dyn Transport>> {
+    let port: u16 = port.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("\"{}\" is not a port number", port)))?;
+    match kind {
+        Kind::Udp => Ok(Box::new(UdpTransport { socket: UdpSocket::bind(SocketAddr::new(ip, port)).map_err(|e| in_use(e, port))?, buffer: protocol::buffer(), group: None })),
+        Kind::Tcp => Ok(Box::new(TcpTransport { stream: None, incoming: None })),
+    }
+}
+
+/// Says who has the port when binding it failed because it's taken, most
+/// likely another Blossom started with the same --port.
+fn in_use(e: Error, port: u16) -> Error {
+    if e.kind() != ErrorKind::AddrInUse {
+        return e;
+    }
+    let pick = "pick another one with --port, or --port 0 to let the system find a free one";
+    let message = match port_owner(port) {
+        Some((pid, name)) if fs::read_to_string("/proc/self/comm").is_ok_and(|own| own.trim() == name) => format!("Port {} is taken by another Blossom (pid {}), {}", port, pid, pick),
+        Some((pid, name)) => format!("Port {} is taken by {} (pid {}), {}", port, name, pid, pick),
+        None => format!("Port {} is already in use, {}", port, pick)
+    };
+    Error::new(ErrorKind::AddrInUse, message)
+}
+
+/// The process with a UDP socket on `port`, by pid and name. It's looked
+/// up in /proc, so this only finds anything on Linux, and only someone
+/// else's processes when running as root, which Blossom does anyway.
+fn port_owner(port: u16) -> Option<(u32, String)> {
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    let inode = ["/proc/net/udp", "/proc/net/udp6"].into_iter()
+        .filter_map(|table| fs::read_to_string(table).ok())
+        .flat_map(|table| table.lines().skip(1).map(|line| line.split_whitespace().map(String::from).collect::<Vec<_>>()).collect::<Vec<_>>())
+        .find(|fields| fields.get(1).and_then(|local| local.rsplit_once(':')).is_some_and(|(_, hex)| u16::from_str_radix(hex, 16) == Ok(port)))
+        .and_then(|fields| fields.get(9).cloned())?;
+    let socket = format!("socket:[{}]", inode);
+    fs::read_dir("/proc").ok()?.flatten().find_map(|process| {
+        let pid: u32 = process.file_name().to_str()?.parse().ok()?;
+        let owns = fs::read_dir(process.path().join("fd")).ok()?.flatten().any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == socket.as_str()));
+        owns.then(|| (pid, fs::read_to_string(process.path().join("comm")).unwrap_or_default().trim().to_string()))
+    })
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    // set when talking to a multicast group or broadcasting instead of a server
+    group: Option<SocketAddr>,
+}
+
+impl Transport for UdpTransport {
+    fn connect(&mut self, addr: &str) -> Result<()> {
+        match addr.parse::<SocketAddr>() {
+            Ok(target) if is_group(target.ip()) => {
+                self.socket = join_group(target)?;
+                self.group = Some(target);
+            }
+            _ => {
+                self.socket.connect(addr)?;
+                self.group = None;
+            }
+        }
+        self.socket.set_nonblocking(true)
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<()> {
+        match self.group {
+            Some(group) => self.socket.send_to(packet, group).map(|_| ()),
+            None => self.socket.send(packet).map(|_| ())
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        match protocol::recv_from(&self.socket, &mut self.buffer) {
+            Ok((size, _)) => Ok(Some(self.buffer[..size].to_vec())),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+/// Whether packets to this address reach everyone listening on it rather than
+/// a single machine. Only the limited broadcast address 255.255.255.255
+/// counts as broadcast, a subnet's broadcast address can't be told from a
+/// host address without knowing the netmask.
+pub fn is_group(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_multicast() || ip.is_broadcast(),
+        IpAddr::V6(ip) => ip.is_multicast(),
+    }
+}
+
+/// A socket that receives what's sent to the group. It has to be bound to
+/// the group's port, since everyone in the group sends to that port.
+fn join_group(group: SocketAddr) -> Result<UdpSocket> {
+    match group.ip() {
+        IpAddr::V4(ip) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))?;
+            if ip.is_broadcast() {
+                socket.set_broadcast(true)?;
+            } else {
+                socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+            }
+            Ok(socket)
+        }
+        IpAddr::V6(ip) => {
+            let socket = UdpSocket::bind(format!("[::]:{}", group.port()))?;
+            // 0 lets the system pick the interface
+            socket.join_multicast_v6(&ip, 0)?;
+            Ok(socket)
+        }
+    }
+}
+
+/// Sends packets over a TCP stream, each one prefixed with its length.
+pub struct TcpTransport {
+    stream: Option<TcpStream>,
+    incoming: Option<Receiver<Result<Vec<u8>>>>,
+}
+
+impl Transport for TcpTransport {
+    fn connect(&mut self, addr: &str) -> Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        // reading blocks, so frames are read on their own thread and picked up from a channel
+        let mut reader = stream.try_clone()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || loop {
+            let frame = read_frame(&mut reader);
+            let failed = frame.is_err();
+            if tx.send(frame).is_err() || failed {
+                break;
+            }
+        });
+
+        self.stream = Some(stream);
+        self.incoming = Some(rx);
+        Ok(())
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<()> {
+        match &self.stream {
+            Some(stream) => write_frame(&mut &*stream, packet),
+            None => Err(Error::new(ErrorKind::NotConnected, "not connected to the server"))
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let incoming = match &self.incoming {
+            Some(incoming) => incoming,
+            None => return Ok(None)
+        };
+        match incoming.try_recv() {
+            Ok(frame) => frame.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(Error::new(ErrorKind::ConnectionAborted, "connection to the server is closed"))
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        match &self.stream {
+            Some(stream) => stream.local_addr(),
+            None => Err(Error::new(ErrorKind::NotConnected, "not connected to the server"))
+        }
+    }
+}
+
+/// Name of the user who keeps `--offline` company.
+pub const ECHO: &str = "echo";
+
+/// Stands in for the network with `--offline`. Whatever is sent comes right
+/// back, the way the server relays it to everyone, and a user called `ECHO`
+/// joins along with us and repeats every message.
+#[derive(Default)]
+pub struct LocalTransport {
+    // sending only borrows the transport
+    queue: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl Transport for LocalTransport {
+    fn connect(&mut self, _addr: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<()> {
+        let mut queue = self.queue.borrow_mut();
+        queue.push_back(packet.to_vec());
+        match Packet::decode(packet) {
+            Some(Packet::Join(_)) => queue.push_back(Packet::Join(ECHO.to_string()).encode()),
+            Some(Packet::Chat { message, sent, .. }) => queue.push_back(Packet::Chat { username: ECHO.to_string(), message, sent }.encode()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.queue.borrow_mut().pop_front())
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Err(Error::new(ErrorKind::NotConnected, "offline"))
+    }
+}
+
+/// Writes a packet with a 4 byte big-endian length in front of it.
+pub fn write_frame(stream: &mut impl Write, packet: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(4 + packet.len());
+    frame.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+    frame.extend_from_slice(packet);
+    stream.write_all(&frame)
+}
+
+/// Reads one packet written by `write_frame`. Frames claiming to be bigger
+/// than a datagram could be are refused rather than allocated.
+pub fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_DATAGRAM {
+        return Err(Error::new(ErrorKind::InvalidData, format!("frame of {} bytes is too big", len)));
+    }
+    let mut packet = vec![0u8; len];
+    stream.read_exact(&mut packet)?;
+    Ok(packet)
+}
@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+
+use crate::presence::summarize;
+
+/// How long someone shows as typing after we last heard they are. Stopping
+/// is announced too, but on UDP that packet can get lost.
+const EXPIRY: Duration = Duration::from_secs(5);
+/// How often to tell the room we're still typing by default, well within
+/// `EXPIRY`.
+pub const REFRESH: Duration = Duration::from_secs(3);
+/// The longest refresh that still gets there before `EXPIRY`, more or less.
+pub const MAX_REFRESH: Duration = Duration::from_secs(4);
+
+/// Who in the room is typing right now.
+#[derive(Default)]
+pub struct Typing {
+    users: Vec<(String, Instant)>,
+}
+
+impl Typing {
+
+    pub fn set(&mut self, username: &str, typing: bool, now: Instant) {
+        self.users.retain(|(name, since)| (typing || name != username) && now.duration_since(*since) < EXPIRY);
+        if !typing {
+            return;
+        }
+        // still typing keeps their place in the line
+        match self.users.iter_mut().find(|(name, _)| name == username) {
+            Some((_, since)) => *since = now,
+            None => self.users.push((username.to_string(), now)),
+        }
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        for (name, _) in self.users.iter_mut() {
+            if name == old {
+                *name = new.to_string();
+            }
+        }
+    }
+
+    /// Everyone typing, in the order they started.
+    pub fn names(&self, now: Instant) -> Vec<String> {
+        self.users.iter().filter(|(_, since)| now.duration_since(*since) < EXPIRY).map(|(name, _)| name.clone()).collect()
+    }
+}
+
+/// "Alice is typing.", "Alice and Bob are typing..", with the dots counting
+/// up with `frame` so it looks alive.
+pub fn indicator(names: &[String], frame: usize) -> String {
+    let verb = if names.len() == 1 { "is" } else { "are" };
+    format!("{} {} typing{}", summarize(names), verb, ".".repeat(frame % 3 + 1))
+}
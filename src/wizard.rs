@@ -0,0 +1,168 @@
+use std::io;
+use ratatui::{buffer::Buffer, crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, layout::{Position, Rect}, style::{Style, Stylize}, text::Line, widgets::{Block, Paragraph, Widget}, DefaultTerminal};
+
+use crate::{app::decode_roomkey, protocol::MAX_USERNAME, transport};
+
+/// What the first run asked for. The room key is empty when starting a room.
+#[derive(Debug, PartialEq)]
+pub struct Answers {
+    pub username: String,
+    pub roomkey: String,
+    pub transport: transport::Kind,
+}
+
+/// What a keypress did to the form.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Continue,
+    Done(Answers),
+    Cancel,
+}
+
+const FIELDS: usize = 3;
+
+/// The form shown on the very first run, before there is a config file.
+/// It asks for a username, a room key to join (or none to start a room) and
+/// the transport.
+#[derive(Default)]
+pub struct Wizard {
+    username: String,
+    roomkey: String,
+    tcp: bool,
+    focus: usize,
+    error: Option<String>,
+}
+
+impl Wizard {
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Step {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Step::Cancel;
+        }
+        match key.code {
+            KeyCode::Esc => return Step::Cancel,
+            KeyCode::Tab | KeyCode::Down => self.focus = (self.focus + 1) % FIELDS,
+            KeyCode::BackTab | KeyCode::Up => self.focus = (self.focus + FIELDS - 1) % FIELDS,
+            KeyCode::Enter if self.focus + 1 < FIELDS => self.focus += 1,
+            KeyCode::Enter => return self.submit(),
+            KeyCode::Left | KeyCode::Right | KeyCode::Char(' ') if self.focus == 2 => self.tcp = !self.tcp,
+            KeyCode::Backspace => {
+                if let Some(field) = self.field() {
+                    field.pop();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(field) = self.field() {
+                    field.push(c);
+                }
+            }
+            _ => {}
+        }
+        Step::Continue
+    }
+
+    fn field(&mut self) -> Option<&mut String> {
+        match self.focus {
+            0 => Some(&mut self.username),
+            1 => Some(&mut self.roomkey),
+            _ => None
+        }
+    }
+
+    fn submit(&mut self) -> Step {
+        let username = self.username.trim().to_string();
+        if username.contains('|') {
+            self.error = Some("Usernames can't contain \"|\"".to_string());
+            self.focus = 0;
+            return Step::Continue;
+        }
+        if username.len() > MAX_USERNAME {
+            self.error = Some(format!("Usernames can be {} bytes long at most", MAX_USERNAME));
+            self.focus = 0;
+            return Step::Continue;
+        }
+        let roomkey = match self.roomkey.trim() {
+            "" => String::new(),
+            roomkey => match decode_roomkey(roomkey) {
+                Ok((roomkey, _)) => roomkey,
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    self.focus = 1;
+                    return Step::Continue;
+                }
+            }
+        };
+        let transport = if self.tcp { transport::Kind::Tcp } else { transport::Kind::Udp };
+        Step::Done(Answers { username, roomkey, transport })
+    }
+}
+
+/// Shows the form until it's filled in, or returns `None` if it was left.
+pub fn run(terminal: &mut DefaultTerminal) -> io::Result<Option<Answers>> {
+    let mut wizard = Wizard::default();
+    loop {
+        terminal.draw(|frame| {
+            frame.render_widget(&wizard, frame.area());
+            if let Some(cursor) = wizard.cursor(frame.area()) {
+                frame.set_cursor_position(cursor);
+            }
+        })?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match wizard.handle_key(key) {
+                Step::Continue => {}
+                Step::Done(answers) => return Ok(Some(answers)),
+                Step::Cancel => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Wizard {
+    // each field takes a bordered box three rows tall, below two rows of explanation
+    fn field_area(area: Rect, index: usize) -> Rect {
+        Rect { x: area.x + 1, y: area.y + 3 + 3 * index as u16, width: area.width.saturating_sub(2), height: 3 }.intersection(area)
+    }
+
+    fn cursor(&self, area: Rect) -> Option<Position> {
+        let text = match self.focus {
+            0 => &self.username,
+            1 => &self.roomkey,
+            _ => return None
+        };
+        let field = Self::field_area(area, self.focus);
+        let col = unicode_width::UnicodeWidthStr::width(text.as_str()) as u16;
+        Some(Position { x: (field.x + 1 + col).min(field.right().saturating_sub(2)), y: field.y + 1 })
+    }
+}
+
+impl Widget for &Wizard {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Block::bordered().title(" Welcome to Blossom ").render(area, buf);
+        let intro = Rect { x: area.x + 2, y: area.y + 1, width: area.width.saturating_sub(4), height: 2 }.intersection(area);
+        Paragraph::new(vec![
+            Line::from("Tab moves between fields, Enter on the last one starts Blossom, Esc quits."),
+            Line::from("The username and transport are saved to the config file, so this isn't asked again.").dark_gray(),
+        ]).render(intro, buf);
+
+        let transport = if self.tcp { "< TCP >  for networks that block UDP" } else { "< UDP >  works best, pick TCP if UDP is blocked" };
+        let fields = [
+            (" Username, empty for a random one ", self.username.as_str()),
+            (" Room key to join, empty to start a room ", self.roomkey.as_str()),
+            (" Transport ", transport),
+        ];
+        for (index, (title, value)) in fields.into_iter().enumerate() {
+            let style = if index == self.focus { Style::new().cyan() } else { Style::new() };
+            Paragraph::new(value)
+                .block(Block::bordered().title(title).border_style(style))
+                .render(Wizard::field_area(area, index), buf);
+        }
+
+        if let Some(error) = &self.error {
+            let row = Wizard::field_area(area, FIELDS);
+            Paragraph::new(error.as_str()).red().render(Rect { height: 1, ..row }.intersection(area), buf);
+        }
+    }
+}
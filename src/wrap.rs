@@ -0,0 +1,118 @@
+use std::ops::Range;
+use ratatui::text::{Line, Span};
+use unicode_width::UnicodeWidthChar;
+
+/// One character of a line and where it lives.
+struct Cell {
+    span: usize,
+    bytes: Range<usize>,
+    width: usize,
+    space: bool,
+}
+
+fn cells(line: &Line) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for (span, s) in line.spans.iter().enumerate() {
+        for (i, c) in s.content.char_indices() {
+            cells.push(Cell { span, bytes: i..i + c.len_utf8(), width: c.width().unwrap_or(0), space: c == ' ' });
+        }
+    }
+    cells
+}
+
+/// Splits the cells into rows no wider than `width`, breaking after the last
+/// space of a row when there is one. The space the row broke at is dropped.
+fn rows(cells: &[Cell], width: usize) -> Vec<Range<usize>> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    let mut space: Option<usize> = None;
+    let mut i = 0;
+
+    while i < cells.len() {
+        // a character wider than the whole row still gets a row of its own
+        if used + cells[i].width > width && i > start {
+            // the space that doesn't fit anymore is a break of its own
+            if cells[i].space {
+                rows.push(start..i);
+                i += 1;
+                start = i;
+                used = 0;
+                space = None;
+                continue;
+            }
+            let (end, next) = match space {
+                Some(space) => (space, space + 1),
+                None => (i, i)
+            };
+            rows.push(start..end);
+            start = next;
+            used = cells[start..i].iter().map(|cell| cell.width).sum();
+            space = cells[start..i].iter().rposition(|cell| cell.space).map(|p| p + start);
+            continue;
+        }
+        if cells[i].space {
+            space = Some(i);
+        }
+        used += cells[i].width;
+        i += 1;
+    }
+    rows.push(start..cells.len());
+    rows
+}
+
+/// How many rows `wrap` would split the line into.
+pub fn row_count(line: &Line, width: usize) -> usize {
+    if width == 0 {
+        return 1;
+    }
+    rows(&cells(line), width).len()
+}
+
+/// Splits the line into rows that fit into `width` terminal cells, keeping
+/// the styles of its spans. Wide characters like CJK and most emoji take two
+/// cells, so they are measured with `unicode-width` instead of being counted
+/// as one.
+pub fn wrap<'a>(line: &'a Line, width: usize) -> Vec<Line<'a>> {
+    let cells = cells(line);
+    let rows = if width == 0 { std::iter::once(0..cells.len()).collect() } else { rows(&cells, width) };
+
+    rows.into_iter().map(|row| {
+        let mut spans: Vec<Span<'a>> = Vec::new();
+        let mut i = row.start;
+        while i < row.end {
+            // take the run of characters coming from the same span in one go
+            let span = cells[i].span;
+            let first = cells[i].bytes.start;
+            while i + 1 < row.end && cells[i + 1].span == span {
+                i += 1;
+            }
+            let source = &line.spans[span];
+            spans.push(Span::styled(&source.content[first..cells[i].bytes.end], source.style));
+            i += 1;
+        }
+        Line { spans, style: line.style, alignment: line.alignment }
+    }).collect()
+}
+
+/// Puts a ⏎ where each newline was, so a message typed over several lines
+/// takes a single one.
+pub fn collapse_lines(spans: Vec<Span<'static>>) -> Vec<Span<'static>> {
+    spans.into_iter().map(|span| Span::styled(span.content.replace('\n', " ⏎ "), span.style)).collect()
+}
+
+/// Splits spans at newlines, giving the spans of each line of a message
+/// that was typed over several lines.
+pub fn split_lines(spans: Vec<Span<'static>>) -> Vec<Vec<Span<'static>>> {
+    let mut lines = vec![Vec::new()];
+    for span in spans {
+        let mut parts = span.content.split('\n');
+        if let Some(first) = parts.next() {
+            lines.last_mut().unwrap().push(Span::styled(first.to_string(), span.style));
+        }
+        for part in parts {
+            lines.push(vec![Span::styled(part.to_string(), span.style)]);
+        }
+    }
+    lines
+}